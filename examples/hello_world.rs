@@ -198,7 +198,7 @@ impl HelloWorld {
             let mut imgui = imgui::Context::create();
             let mut winit_platform = WinitPlatform::init(&mut imgui);
 
-            winit_platform.attach_window(imgui.io_mut(), window, HiDpiMode::Rounded);
+            winit_platform.attach_window(imgui.io_mut(), window, HiDpiMode::Default);
 
             let hidpi_factor = winit_platform.hidpi_factor();
             let font_size = (13.0 * hidpi_factor) as f32;
@@ -289,6 +289,7 @@ impl HelloWorld {
             // Execute the command list.
             let command_list = Some(resources.command_list.cast().unwrap());
             unsafe { resources.command_queue.ExecuteCommandLists(&[command_list]) };
+            resources.renderer.signal(&resources.command_queue).unwrap();
 
             // Present the frame.
             unsafe { resources.swap_chain.Present(1, DXGI_PRESENT(0)) }
@@ -332,7 +333,8 @@ fn populate_command_list(resources: &mut Resources) {
         command_list.SetDescriptorHeaps(&[Some(resources.srv_heap.clone())]);
         resources
             .renderer
-            .render_draw_data(resources.imgui.render(), command_list);
+            .render_draw_data(resources.imgui.render(), command_list)
+            .unwrap();
 
         command_list.ResourceBarrier(&[transition_barrier(
             &resources.render_targets[resources.frame_index as usize],
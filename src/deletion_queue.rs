@@ -0,0 +1,77 @@
+//
+// Keeps objects the GPU may still be using alive until the frames that used
+// them have finished.
+//
+
+use std::any::Any;
+
+use windows::core::Result;
+
+use crate::FrameFence;
+
+pub(crate) struct DeletionQueue {
+    // The most recently recorded frame, and the fence value at which it
+    // completes.  Objects retired now may have been used by it.
+    last_used_frame: usize,
+    fence_value: u64,
+    pending: Vec<PendingDeletion>,
+}
+
+struct PendingDeletion {
+    _object: Box<dyn Any + Send>, // keep reference alive
+    last_used_frame: usize,
+    fence_value: u64,
+}
+
+impl Default for DeletionQueue {
+    fn default() -> Self {
+        DeletionQueue {
+            last_used_frame: usize::MAX,
+            fence_value: 0,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl DeletionQueue {
+    // Releases the objects the GPU has finished with, and starts recording
+    // frame_index, which completes when the fence reaches its next value.
+    pub(crate) fn begin_frame(
+        &mut self,
+        frame_fence: &FrameFence,
+        frame_index: usize,
+        num_frames_in_flight: usize,
+    ) {
+        self.pending.retain(|pending| {
+            !frame_fence.has_completed(
+                frame_index,
+                num_frames_in_flight,
+                pending.last_used_frame,
+                pending.fence_value,
+            )
+        });
+
+        self.last_used_frame = frame_index;
+        self.fence_value = frame_fence.next_value;
+    }
+
+    // Drops `object` once the frames recorded so far have finished.
+    pub(crate) fn retire(&mut self, object: impl Any + Send) {
+        self.pending.push(PendingDeletion {
+            _object: Box::new(object),
+            last_used_frame: self.last_used_frame,
+            fence_value: self.fence_value,
+        });
+    }
+
+    // Waits for the frames submitted so far and releases everything, for when
+    // the fence is about to be replaced.
+    pub(crate) fn flush(&mut self, frame_fence: &FrameFence) -> Result<()> {
+        if !self.pending.is_empty() {
+            frame_fence.wait_for_submitted_frames()?;
+        }
+
+        *self = DeletionQueue::default();
+        Ok(())
+    }
+}
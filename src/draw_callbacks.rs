@@ -0,0 +1,79 @@
+//
+// Rust closures that are called while a draw list is rendered, with the
+// command list and the state it's drawn with.
+//
+
+use std::ffi::c_void;
+
+use imgui::sys::{igGetWindowDrawList, ImDrawCmd, ImDrawList, ImDrawList_AddCallback};
+use windows::Win32::{
+    Foundation::RECT,
+    Graphics::Direct3D12::{ID3D12GraphicsCommandList, D3D12_VIEWPORT},
+};
+
+/// What a callback added with `Renderer::add_draw_callback` is called with.
+#[non_exhaustive]
+pub struct DrawCallbackContext<'a> {
+    /// The command list passed to `render_draw_data`.
+    pub command_list: &'a ID3D12GraphicsCommandList,
+
+    /// The callback's clip rectangle, projected into render target space.
+    pub scissor_rect: RECT,
+
+    /// The viewport the draw data is rendered with.
+    pub viewport: D3D12_VIEWPORT,
+
+    /// The renderer's frame counter, which increases by one each time draw
+    /// data is rendered.
+    pub frame_index: usize,
+}
+
+type DrawCallback = Box<dyn FnMut(&DrawCallbackContext) + Send>;
+
+// The callbacks added since the last new_frame.  Each is added to its draw
+// list as the trampoline, with its index as the callback data.
+#[derive(Default)]
+pub(crate) struct DrawCallbacks {
+    callbacks: Vec<DrawCallback>,
+}
+
+impl DrawCallbacks {
+    pub(crate) fn clear(&mut self) {
+        self.callbacks.clear();
+    }
+
+    // Adds the callback to the current window's draw list.  Must be called
+    // between imgui's new_frame and render.
+    pub(crate) fn add(&mut self, callback: impl FnMut(&DrawCallbackContext) + Send + 'static) {
+        let index = self.callbacks.len();
+        self.callbacks.push(Box::new(callback));
+
+        unsafe {
+            ImDrawList_AddCallback(
+                igGetWindowDrawList(),
+                Some(draw_callback_trampoline),
+                index as *mut c_void,
+            );
+        }
+    }
+
+    // Whether a draw command's callback was added by `add`.
+    pub(crate) fn is_draw_callback(
+        callback: unsafe extern "C" fn(*const ImDrawList, *const ImDrawCmd),
+    ) -> bool {
+        callback as *const () == draw_callback_trampoline as *const ()
+    }
+
+    // Calls the closure behind a draw command added by `add`.
+    pub(crate) unsafe fn call(&mut self, raw_cmd: *const ImDrawCmd, context: &DrawCallbackContext) {
+        let index = (*raw_cmd).UserCallbackData as usize;
+        if let Some(callback) = self.callbacks.get_mut(index) {
+            callback(context);
+        }
+    }
+}
+
+// Identifies the draw commands added by DrawCallbacks::add.  The renderer
+// calls the closure itself, so this is never called by it, and does nothing
+// if another renderer draws the draw list.
+unsafe extern "C" fn draw_callback_trampoline(_: *const ImDrawList, _: *const ImDrawCmd) {}
@@ -0,0 +1,172 @@
+//
+// Shader compilation with DXC, for shaders that need shader model 6.  The
+// compiler is loaded from dxcompiler.dll when it's first needed, so apps that
+// don't use it don't need to ship the dll, and then stays loaded.
+//
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, OnceLock},
+};
+
+use windows::{
+    core::{implement, s, w, Error, Interface, Result, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{FreeLibrary, E_FAIL},
+        Graphics::Direct3D::{
+            Dxc::{
+                CLSID_DxcCompiler, CLSID_DxcUtils, DxcBuffer, DxcCreateInstanceProc, IDxcBlob,
+                IDxcBlobUtf8, IDxcCompiler3, IDxcIncludeHandler, IDxcIncludeHandler_Impl,
+                IDxcResult, IDxcUtils, DXC_CP_UTF8, DXC_OUT_ERRORS, DXC_OUT_OBJECT,
+            },
+            ID3DBlob,
+        },
+        System::LibraryLoader::{GetProcAddress, LoadLibraryW},
+    },
+};
+
+use crate::{ShaderCompileOptions, ShaderIncludeHandler};
+
+pub(crate) fn compile_shader(
+    hlsl: &str,
+    entry_point: &str,
+    target: &str,
+    defines: &[(String, String)],
+    options: &ShaderCompileOptions,
+) -> Result<ID3DBlob> {
+    let compiler: IDxcCompiler3 = create_instance(&CLSID_DxcCompiler)?;
+
+    let source = DxcBuffer {
+        Ptr: hlsl.as_ptr() as *const _,
+        Size: hlsl.len(),
+        Encoding: DXC_CP_UTF8.0,
+    };
+
+    let entry_point = HSTRING::from(entry_point);
+    let target = HSTRING::from(target);
+    let defines: Vec<HSTRING> = defines
+        .iter()
+        .map(|(name, value)| HSTRING::from(format!("{name}={value}")))
+        .collect();
+    let extra_arguments: Vec<HSTRING> = options
+        .dxc_arguments
+        .iter()
+        .map(|argument| HSTRING::from(argument.as_str()))
+        .collect();
+
+    let mut arguments = vec![
+        w!("-E"),
+        PCWSTR(entry_point.as_ptr()),
+        w!("-T"),
+        PCWSTR(target.as_ptr()),
+    ];
+    for define in &defines {
+        arguments.extend([w!("-D"), PCWSTR(define.as_ptr())]);
+    }
+    arguments.extend(
+        extra_arguments
+            .iter()
+            .map(|argument| PCWSTR(argument.as_ptr())),
+    );
+
+    let include_handler: Option<IDxcIncludeHandler> = match &options.include_handler {
+        Some(handler) => Some(
+            IncludeHandler {
+                handler: handler.clone(),
+                utils: create_instance(&CLSID_DxcUtils)?,
+            }
+            .into(),
+        ),
+        None => None,
+    };
+
+    unsafe {
+        let result: IDxcResult =
+            compiler.Compile(&source, Some(&arguments), include_handler.as_ref())?;
+
+        if let Err(error) = result.GetStatus()?.ok() {
+            let mut errors: Option<IDxcBlobUtf8> = None;
+            if result
+                .GetOutput(DXC_OUT_ERRORS, std::ptr::null_mut(), &mut errors)
+                .is_ok()
+            {
+                if let Some(errors) = errors {
+                    let messages = std::slice::from_raw_parts(
+                        errors.GetStringPointer().0,
+                        errors.GetStringLength(),
+                    );
+                    let _ = io::stdout().write_all(messages);
+                    return Err(Error::new(error.code(), String::from_utf8_lossy(messages)));
+                }
+            }
+            return Err(error);
+        }
+
+        let mut object: Option<IDxcBlob> = None;
+        result.GetOutput(DXC_OUT_OBJECT, std::ptr::null_mut(), &mut object)?;
+
+        // IDxcBlob and ID3DBlob are the same interface
+        object.ok_or_else(|| Error::from(E_FAIL))?.cast()
+    }
+}
+
+// Adapts a ShaderIncludeHandler to DXC's include interface.
+#[implement(IDxcIncludeHandler)]
+struct IncludeHandler {
+    handler: Arc<dyn ShaderIncludeHandler>,
+    utils: IDxcUtils,
+}
+
+impl IDxcIncludeHandler_Impl for IncludeHandler_Impl {
+    fn LoadSource(&self, file_name: &PCWSTR) -> Result<IDxcBlob> {
+        let file_name = unsafe { file_name.to_string() }.map_err(|_| Error::from(E_FAIL))?;
+        let contents = self
+            .handler
+            .load(&file_name)
+            .ok_or_else(|| Error::from(E_FAIL))?;
+
+        // CreateBlob copies the data
+        unsafe {
+            self.utils
+                .CreateBlob(
+                    contents.as_ptr() as *const _,
+                    contents.len() as u32,
+                    DXC_CP_UTF8,
+                )?
+                .cast()
+        }
+    }
+}
+
+fn create_instance<T: Interface>(clsid: &windows::core::GUID) -> Result<T> {
+    let create_instance = dxc_create_instance()?.ok_or_else(|| Error::from(E_FAIL))?;
+    unsafe {
+        let mut instance = std::ptr::null_mut();
+        create_instance(clsid, &T::IID, &mut instance).ok()?;
+        Ok(T::from_raw(instance))
+    }
+}
+
+// Finds dxcompiler.dll's DxcCreateInstance the first time it's needed.  The
+// dll is never unloaded after that, so the function stays valid.  Failures
+// aren't cached, so a dll that's missing at first can still be loaded later.
+fn dxc_create_instance() -> Result<DxcCreateInstanceProc> {
+    static CREATE_INSTANCE: OnceLock<DxcCreateInstanceProc> = OnceLock::new();
+
+    if let Some(create_instance) = CREATE_INSTANCE.get() {
+        return Ok(*create_instance);
+    }
+
+    unsafe {
+        let module = LoadLibraryW(w!("dxcompiler.dll"))?;
+        let create_instance: DxcCreateInstanceProc =
+            std::mem::transmute(GetProcAddress(module, s!("DxcCreateInstance")));
+        if create_instance.is_none() {
+            let error = Error::from_win32();
+            let _ = FreeLibrary(module);
+            return Err(error);
+        }
+
+        Ok(*CREATE_INSTANCE.get_or_init(|| create_instance))
+    }
+}
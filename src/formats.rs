@@ -0,0 +1,186 @@
+//
+// Information about DXGI formats, for showing textures and their contents.
+//
+
+use windows::Win32::Graphics::Dxgi::Common::{
+    DXGI_FORMAT, DXGI_FORMAT_A8_UNORM, DXGI_FORMAT_B8G8R8A8_TYPELESS, DXGI_FORMAT_B8G8R8A8_UNORM,
+    DXGI_FORMAT_B8G8R8A8_UNORM_SRGB, DXGI_FORMAT_BC1_TYPELESS, DXGI_FORMAT_BC1_UNORM,
+    DXGI_FORMAT_BC1_UNORM_SRGB, DXGI_FORMAT_BC2_UNORM, DXGI_FORMAT_BC3_UNORM,
+    DXGI_FORMAT_BC3_UNORM_SRGB, DXGI_FORMAT_BC4_UNORM, DXGI_FORMAT_BC5_SNORM,
+    DXGI_FORMAT_BC5_UNORM, DXGI_FORMAT_BC6H_TYPELESS, DXGI_FORMAT_BC6H_UF16, DXGI_FORMAT_BC7_UNORM,
+    DXGI_FORMAT_BC7_UNORM_SRGB, DXGI_FORMAT_D16_UNORM, DXGI_FORMAT_D24_UNORM_S8_UINT,
+    DXGI_FORMAT_D32_FLOAT, DXGI_FORMAT_D32_FLOAT_S8X24_UINT, DXGI_FORMAT_R10G10B10A2_TYPELESS,
+    DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_R11G11B10_FLOAT, DXGI_FORMAT_R16G16B16A16_FLOAT,
+    DXGI_FORMAT_R16G16B16A16_TYPELESS, DXGI_FORMAT_R16G16B16A16_UNORM, DXGI_FORMAT_R16G16_FLOAT,
+    DXGI_FORMAT_R16_FLOAT, DXGI_FORMAT_R16_TYPELESS, DXGI_FORMAT_R16_UNORM,
+    DXGI_FORMAT_R24G8_TYPELESS, DXGI_FORMAT_R24_UNORM_X8_TYPELESS, DXGI_FORMAT_R32G32B32A32_FLOAT,
+    DXGI_FORMAT_R32G32B32A32_TYPELESS, DXGI_FORMAT_R32G32B32A32_UINT, DXGI_FORMAT_R32G32B32_FLOAT,
+    DXGI_FORMAT_R32G32_FLOAT, DXGI_FORMAT_R32G8X24_TYPELESS, DXGI_FORMAT_R32_FLOAT,
+    DXGI_FORMAT_R32_FLOAT_X8X24_TYPELESS, DXGI_FORMAT_R32_TYPELESS, DXGI_FORMAT_R32_UINT,
+    DXGI_FORMAT_R8G8B8A8_TYPELESS, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+    DXGI_FORMAT_R8G8_UNORM, DXGI_FORMAT_R8_UNORM,
+};
+
+pub(crate) fn format_name(format: DXGI_FORMAT) -> String {
+    let name = match format {
+        DXGI_FORMAT_R32G32B32A32_TYPELESS => "R32G32B32A32_TYPELESS",
+        DXGI_FORMAT_R32G32B32A32_FLOAT => "R32G32B32A32_FLOAT",
+        DXGI_FORMAT_R32G32B32A32_UINT => "R32G32B32A32_UINT",
+        DXGI_FORMAT_R32G32B32_FLOAT => "R32G32B32_FLOAT",
+        DXGI_FORMAT_R16G16B16A16_TYPELESS => "R16G16B16A16_TYPELESS",
+        DXGI_FORMAT_R16G16B16A16_FLOAT => "R16G16B16A16_FLOAT",
+        DXGI_FORMAT_R16G16B16A16_UNORM => "R16G16B16A16_UNORM",
+        DXGI_FORMAT_R32G32_FLOAT => "R32G32_FLOAT",
+        DXGI_FORMAT_R32G8X24_TYPELESS => "R32G8X24_TYPELESS",
+        DXGI_FORMAT_D32_FLOAT_S8X24_UINT => "D32_FLOAT_S8X24_UINT",
+        DXGI_FORMAT_R10G10B10A2_TYPELESS => "R10G10B10A2_TYPELESS",
+        DXGI_FORMAT_R10G10B10A2_UNORM => "R10G10B10A2_UNORM",
+        DXGI_FORMAT_R11G11B10_FLOAT => "R11G11B10_FLOAT",
+        DXGI_FORMAT_R8G8B8A8_TYPELESS => "R8G8B8A8_TYPELESS",
+        DXGI_FORMAT_R8G8B8A8_UNORM => "R8G8B8A8_UNORM",
+        DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => "R8G8B8A8_UNORM_SRGB",
+        DXGI_FORMAT_R16G16_FLOAT => "R16G16_FLOAT",
+        DXGI_FORMAT_R32_TYPELESS => "R32_TYPELESS",
+        DXGI_FORMAT_D32_FLOAT => "D32_FLOAT",
+        DXGI_FORMAT_R32_FLOAT => "R32_FLOAT",
+        DXGI_FORMAT_R32_UINT => "R32_UINT",
+        DXGI_FORMAT_R24G8_TYPELESS => "R24G8_TYPELESS",
+        DXGI_FORMAT_D24_UNORM_S8_UINT => "D24_UNORM_S8_UINT",
+        DXGI_FORMAT_R8G8_UNORM => "R8G8_UNORM",
+        DXGI_FORMAT_R16_TYPELESS => "R16_TYPELESS",
+        DXGI_FORMAT_R16_FLOAT => "R16_FLOAT",
+        DXGI_FORMAT_D16_UNORM => "D16_UNORM",
+        DXGI_FORMAT_R16_UNORM => "R16_UNORM",
+        DXGI_FORMAT_R8_UNORM => "R8_UNORM",
+        DXGI_FORMAT_A8_UNORM => "A8_UNORM",
+        DXGI_FORMAT_BC1_UNORM => "BC1_UNORM",
+        DXGI_FORMAT_BC1_UNORM_SRGB => "BC1_UNORM_SRGB",
+        DXGI_FORMAT_BC2_UNORM => "BC2_UNORM",
+        DXGI_FORMAT_BC3_UNORM => "BC3_UNORM",
+        DXGI_FORMAT_BC3_UNORM_SRGB => "BC3_UNORM_SRGB",
+        DXGI_FORMAT_BC4_UNORM => "BC4_UNORM",
+        DXGI_FORMAT_BC5_UNORM => "BC5_UNORM",
+        DXGI_FORMAT_BC6H_UF16 => "BC6H_UF16",
+        DXGI_FORMAT_BC7_UNORM => "BC7_UNORM",
+        DXGI_FORMAT_BC7_UNORM_SRGB => "BC7_UNORM_SRGB",
+        DXGI_FORMAT_B8G8R8A8_TYPELESS => "B8G8R8A8_TYPELESS",
+        DXGI_FORMAT_B8G8R8A8_UNORM => "B8G8R8A8_UNORM",
+        DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => "B8G8R8A8_UNORM_SRGB",
+        _ => return format!("DXGI_FORMAT({})", format.0),
+    };
+
+    name.to_string()
+}
+
+// A format that typeless or depth resources can be viewed with.  Other formats
+// are returned unchanged.
+pub(crate) fn readable_format(format: DXGI_FORMAT) -> DXGI_FORMAT {
+    match format {
+        DXGI_FORMAT_R32G32B32A32_TYPELESS => DXGI_FORMAT_R32G32B32A32_FLOAT,
+        DXGI_FORMAT_R16G16B16A16_TYPELESS => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        DXGI_FORMAT_R32G8X24_TYPELESS | DXGI_FORMAT_D32_FLOAT_S8X24_UINT => {
+            DXGI_FORMAT_R32_FLOAT_X8X24_TYPELESS
+        }
+        DXGI_FORMAT_R10G10B10A2_TYPELESS => DXGI_FORMAT_R10G10B10A2_UNORM,
+        DXGI_FORMAT_R8G8B8A8_TYPELESS => DXGI_FORMAT_R8G8B8A8_UNORM,
+        DXGI_FORMAT_R32_TYPELESS | DXGI_FORMAT_D32_FLOAT => DXGI_FORMAT_R32_FLOAT,
+        DXGI_FORMAT_R24G8_TYPELESS | DXGI_FORMAT_D24_UNORM_S8_UINT => {
+            DXGI_FORMAT_R24_UNORM_X8_TYPELESS
+        }
+        DXGI_FORMAT_R16_TYPELESS | DXGI_FORMAT_D16_UNORM => DXGI_FORMAT_R16_UNORM,
+        DXGI_FORMAT_B8G8R8A8_TYPELESS => DXGI_FORMAT_B8G8R8A8_UNORM,
+        _ => format,
+    }
+}
+
+// True for the BC1 to BC7 formats, which are stored in 4x4 blocks.
+pub(crate) fn is_block_compressed(format: DXGI_FORMAT) -> bool {
+    (DXGI_FORMAT_BC1_TYPELESS.0..=DXGI_FORMAT_BC5_SNORM.0).contains(&format.0)
+        || (DXGI_FORMAT_BC6H_TYPELESS.0..=DXGI_FORMAT_BC7_UNORM_SRGB.0).contains(&format.0)
+}
+
+// Formats a single texel's channels, or returns None for formats that aren't
+// understood.  `data` holds at least 16 bytes.
+pub(crate) fn decode_texel(format: DXGI_FORMAT, data: &[u8]) -> Option<String> {
+    let unorm8 = |index: usize| data[index] as f32 / 255.0;
+    let unorm16 =
+        |index: usize| u16::from_le_bytes([data[index], data[index + 1]]) as f32 / 65535.0;
+    let float16 = |index: usize| f16_to_f32(u16::from_le_bytes([data[index], data[index + 1]]));
+    let float32 = |index: usize| {
+        f32::from_le_bytes([
+            data[index],
+            data[index + 1],
+            data[index + 2],
+            data[index + 3],
+        ])
+    };
+
+    let channels: Vec<f32> = match readable_format(format) {
+        DXGI_FORMAT_R8G8B8A8_UNORM | DXGI_FORMAT_R8G8B8A8_UNORM_SRGB => {
+            (0..4).map(unorm8).collect()
+        }
+        DXGI_FORMAT_B8G8R8A8_UNORM | DXGI_FORMAT_B8G8R8A8_UNORM_SRGB => {
+            [2, 1, 0, 3].into_iter().map(unorm8).collect()
+        }
+        DXGI_FORMAT_R8G8_UNORM => (0..2).map(unorm8).collect(),
+        DXGI_FORMAT_R8_UNORM | DXGI_FORMAT_A8_UNORM => vec![unorm8(0)],
+        DXGI_FORMAT_R16G16B16A16_UNORM => (0..4).map(|c| unorm16(c * 2)).collect(),
+        DXGI_FORMAT_R16_UNORM => vec![unorm16(0)],
+        DXGI_FORMAT_R16G16B16A16_FLOAT => (0..4).map(|c| float16(c * 2)).collect(),
+        DXGI_FORMAT_R16G16_FLOAT => (0..2).map(|c| float16(c * 2)).collect(),
+        DXGI_FORMAT_R16_FLOAT => vec![float16(0)],
+        DXGI_FORMAT_R32G32B32A32_FLOAT => (0..4).map(|c| float32(c * 4)).collect(),
+        DXGI_FORMAT_R32G32B32_FLOAT => (0..3).map(|c| float32(c * 4)).collect(),
+        DXGI_FORMAT_R32G32_FLOAT => (0..2).map(|c| float32(c * 4)).collect(),
+        DXGI_FORMAT_R32_FLOAT | DXGI_FORMAT_R32_FLOAT_X8X24_TYPELESS => vec![float32(0)],
+        DXGI_FORMAT_R10G10B10A2_UNORM => {
+            let packed = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            vec![
+                (packed & 0x3ff) as f32 / 1023.0,
+                ((packed >> 10) & 0x3ff) as f32 / 1023.0,
+                ((packed >> 20) & 0x3ff) as f32 / 1023.0,
+                (packed >> 30) as f32 / 3.0,
+            ]
+        }
+        DXGI_FORMAT_R32_UINT => {
+            return Some(format!(
+                "R {}",
+                u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+            ))
+        }
+        _ => return None,
+    };
+
+    Some(
+        ["R", "G", "B", "A"]
+            .iter()
+            .zip(channels)
+            .map(|(name, value)| format!("{name} {value:.3}"))
+            .collect::<Vec<_>>()
+            .join("  "),
+    )
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+pub(crate) fn format_memory_size(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    } else {
+        format!("{:.1} KiB", bytes as f64 / KIB as f64)
+    }
+}
@@ -0,0 +1,130 @@
+//
+// Shader compilation with FXC, for shader model 5.1.
+//
+
+use std::{
+    cell::RefCell,
+    ffi::{c_void, CString},
+    io::{self, Write},
+};
+
+use windows::{
+    core::{Error, Result, PCSTR},
+    Win32::{
+        Foundation::E_FAIL,
+        Graphics::Direct3D::{
+            Fxc::D3DCompile, ID3DBlob, ID3DInclude, ID3DInclude_Impl, D3D_INCLUDE_TYPE,
+            D3D_SHADER_MACRO,
+        },
+    },
+};
+
+use crate::{ShaderCompileOptions, ShaderIncludeHandler};
+
+pub(crate) fn compile_shader(
+    hlsl: &str,
+    entry_point: &str,
+    target: &str,
+    defines: &[(String, String)],
+    options: &ShaderCompileOptions,
+) -> Result<ID3DBlob> {
+    let entry_point = CString::new(entry_point).map_err(|_| Error::from(E_FAIL))?;
+    let target = CString::new(target).map_err(|_| Error::from(E_FAIL))?;
+
+    let defines = defines
+        .iter()
+        .map(|(name, value)| Ok((CString::new(name.as_str())?, CString::new(value.as_str())?)))
+        .collect::<std::result::Result<Vec<_>, std::ffi::NulError>>()
+        .map_err(|_| Error::from(E_FAIL))?;
+
+    // The list of macros is terminated by an empty entry
+    let macros: Vec<D3D_SHADER_MACRO> = defines
+        .iter()
+        .map(|(name, value)| D3D_SHADER_MACRO {
+            Name: PCSTR(name.as_ptr() as *const u8),
+            Definition: PCSTR(value.as_ptr() as *const u8),
+        })
+        .chain(std::iter::once(D3D_SHADER_MACRO::default()))
+        .collect();
+
+    let include_handler = options
+        .include_handler
+        .as_ref()
+        .map(|handler| IncludeHandler {
+            handler: handler.as_ref(),
+            open_files: RefCell::new(Vec::new()),
+        });
+    let include = include_handler.as_ref().map(ID3DInclude::new);
+
+    let mut shader = None;
+    let mut errors: Option<ID3DBlob> = None;
+    let result = unsafe {
+        D3DCompile(
+            hlsl.as_ptr() as *const c_void,
+            hlsl.len(),
+            None,
+            Some(macros.as_ptr()),
+            include.as_deref(),
+            PCSTR(entry_point.as_ptr() as *const u8),
+            PCSTR(target.as_ptr() as *const u8),
+            options.fxc_flags,
+            0,
+            &mut shader,
+            Some(&mut errors),
+        )
+    }
+    .map(|()| shader.unwrap());
+
+    match (result, errors) {
+        (Err(error), Some(error_blob)) => unsafe {
+            let messages = std::slice::from_raw_parts(
+                error_blob.GetBufferPointer() as *const u8,
+                error_blob.GetBufferSize(),
+            );
+            let _ = io::stdout().write_all(messages);
+            Err(Error::new(
+                error.code(),
+                String::from_utf8_lossy(messages).trim_end_matches('\0'),
+            ))
+        },
+        (result, _) => result,
+    }
+}
+
+// Adapts a ShaderIncludeHandler to FXC's include interface.  FXC hands the
+// data back to Close, so the loaded files are kept alive until then.
+struct IncludeHandler<'a> {
+    handler: &'a dyn ShaderIncludeHandler,
+    open_files: RefCell<Vec<Vec<u8>>>,
+}
+
+impl ID3DInclude_Impl for IncludeHandler<'_> {
+    fn Open(
+        &self,
+        _include_type: D3D_INCLUDE_TYPE,
+        file_name: &PCSTR,
+        _parent_data: *const c_void,
+        data: *mut *mut c_void,
+        bytes: *mut u32,
+    ) -> Result<()> {
+        let file_name = unsafe { file_name.to_string() }.map_err(|_| Error::from(E_FAIL))?;
+        let contents = self
+            .handler
+            .load(&file_name)
+            .ok_or_else(|| Error::from(E_FAIL))?;
+
+        unsafe {
+            *data = contents.as_ptr() as *mut c_void;
+            *bytes = contents.len() as u32;
+        }
+        self.open_files.borrow_mut().push(contents);
+        Ok(())
+    }
+
+    fn Close(&self, data: *const c_void) -> Result<()> {
+        self.open_files
+            .borrow_mut()
+            .retain(|contents| contents.as_ptr() as *const c_void != data);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//
+// Measures how long the GPU spends on the UI pass using timestamp queries.
+//
+
+use windows::{
+    core::{Result, HSTRING},
+    Win32::Graphics::{
+        Direct3D12::{
+            ID3D12Device, ID3D12GraphicsCommandList, ID3D12QueryHeap, ID3D12Resource,
+            D3D12_HEAP_FLAG_NONE, D3D12_HEAP_PROPERTIES, D3D12_HEAP_TYPE_READBACK,
+            D3D12_QUERY_HEAP_DESC, D3D12_QUERY_HEAP_TYPE_TIMESTAMP, D3D12_QUERY_TYPE_TIMESTAMP,
+            D3D12_RANGE, D3D12_RESOURCE_DESC, D3D12_RESOURCE_DIMENSION_BUFFER,
+            D3D12_RESOURCE_STATE_COPY_DEST, D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        },
+        Dxgi::Common::DXGI_SAMPLE_DESC,
+    },
+};
+
+const TIMESTAMP_SIZE: usize = std::mem::size_of::<u64>();
+
+pub(crate) struct GpuTimer {
+    query_heap: ID3D12QueryHeap,
+    timestamp_frequency: u64,
+    frames: Vec<TimerFrame>,
+    last_time_ms: Option<f64>,
+}
+
+// Each frame in flight resolves its pair of timestamps into its own readback
+// buffer, which is read the next time that frame's resources are used.
+struct TimerFrame {
+    readback_buffer: ID3D12Resource,
+    pending: bool,
+}
+
+impl GpuTimer {
+    pub(crate) fn new(
+        device: &ID3D12Device,
+        timestamp_frequency: u64,
+        num_frames_in_flight: usize,
+    ) -> Result<Self> {
+        let mut query_heap: Option<ID3D12QueryHeap> = None;
+        unsafe {
+            device.CreateQueryHeap(
+                &D3D12_QUERY_HEAP_DESC {
+                    Type: D3D12_QUERY_HEAP_TYPE_TIMESTAMP,
+                    Count: (num_frames_in_flight * 2) as u32,
+                    NodeMask: 0,
+                },
+                &mut query_heap,
+            )?;
+        }
+        let query_heap = query_heap.unwrap();
+
+        let frames = (0..num_frames_in_flight)
+            .map(|i| {
+                let readback_buffer = Self::create_readback_buffer(device)?;
+                unsafe {
+                    readback_buffer.SetName(&HSTRING::from(format!("imgui timestamps {}", i)))?;
+                }
+
+                Ok(TimerFrame {
+                    readback_buffer,
+                    pending: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(GpuTimer {
+            query_heap,
+            timestamp_frequency,
+            frames,
+            last_time_ms: None,
+        })
+    }
+
+    pub(crate) fn last_time_ms(&self) -> Option<f64> {
+        self.last_time_ms
+    }
+
+    pub(crate) unsafe fn begin(
+        &mut self,
+        frame_index: usize,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+    ) {
+        // The GPU has finished with this frame's previous use, so its results
+        // are ready to read
+        if self.frames[frame_index].pending {
+            if let Some(time_ms) = self.read_time_ms(frame_index) {
+                self.last_time_ms = Some(time_ms);
+            }
+            self.frames[frame_index].pending = false;
+        }
+
+        graphics_command_list.EndQuery(
+            &self.query_heap,
+            D3D12_QUERY_TYPE_TIMESTAMP,
+            (frame_index * 2) as u32,
+        );
+    }
+
+    pub(crate) unsafe fn end(
+        &mut self,
+        frame_index: usize,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+    ) {
+        let frame = &mut self.frames[frame_index];
+
+        graphics_command_list.EndQuery(
+            &self.query_heap,
+            D3D12_QUERY_TYPE_TIMESTAMP,
+            (frame_index * 2 + 1) as u32,
+        );
+        graphics_command_list.ResolveQueryData(
+            &self.query_heap,
+            D3D12_QUERY_TYPE_TIMESTAMP,
+            (frame_index * 2) as u32,
+            2,
+            &frame.readback_buffer,
+            0,
+        );
+
+        frame.pending = true;
+    }
+
+    unsafe fn read_time_ms(&self, frame_index: usize) -> Option<f64> {
+        let readback_buffer = &self.frames[frame_index].readback_buffer;
+
+        let mut mapped = std::ptr::null_mut();
+        readback_buffer
+            .Map(
+                0,
+                Some(&D3D12_RANGE {
+                    Begin: 0,
+                    End: TIMESTAMP_SIZE * 2,
+                }),
+                Some(&mut mapped),
+            )
+            .ok()?;
+        let timestamps = std::ptr::read_unaligned(mapped as *const [u64; 2]);
+        readback_buffer.Unmap(0, Some(&D3D12_RANGE::default()));
+
+        let ticks = timestamps[1].checked_sub(timestamps[0])?;
+        Some(ticks as f64 * 1000.0 / self.timestamp_frequency as f64)
+    }
+
+    fn create_readback_buffer(device: &ID3D12Device) -> Result<ID3D12Resource> {
+        let mut resource: Option<ID3D12Resource> = None;
+
+        unsafe {
+            device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_READBACK,
+                    ..Default::default()
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &D3D12_RESOURCE_DESC {
+                    Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+                    Width: (TIMESTAMP_SIZE * 2) as u64,
+                    Height: 1,
+                    DepthOrArraySize: 1,
+                    MipLevels: 1,
+                    SampleDesc: DXGI_SAMPLE_DESC {
+                        Count: 1,
+                        Quality: 0,
+                    },
+                    Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                    ..Default::default()
+                },
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                None,
+                &mut resource,
+            )?;
+        }
+
+        Ok(resource.unwrap())
+    }
+}
@@ -3,73 +3,124 @@
 // https://github.com/ocornut/imgui/blob/master/backends/imgui_impl_dx12.h
 //
 
-use std::{
-    ffi::c_void,
-    io::{self, Write},
-};
+use std::ffi::c_void;
 
 use imgui::{
     internal::RawWrapper, BackendFlags, Context, DrawCmd, DrawData, DrawIdx, DrawVert, TextureId,
+    Ui,
 };
 
 use offset::offset_of;
 //
 use windows::{
-    core::{s, w, Interface, Result, HSTRING, PCSTR},
+    core::{h, s, Error, Interface, Result, HSTRING},
     Win32::{
-        Foundation::{CloseHandle, FALSE, RECT, TRUE},
+        Foundation::{CloseHandle, E_INVALIDARG, RECT},
         Graphics::{
-            Direct3D::{Fxc::D3DCompile, ID3DBlob, D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST},
+            Direct3D::{ID3DBlob, WKPDID_D3DDebugObjectNameW, D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST},
             Direct3D12::{
-                D3D12SerializeRootSignature, ID3D12CommandAllocator, ID3D12CommandQueue,
-                ID3D12Device, ID3D12Fence, ID3D12GraphicsCommandList, ID3D12PipelineState,
-                ID3D12Resource, ID3D12RootSignature, D3D12_BLEND_DESC, D3D12_BLEND_INV_SRC_ALPHA,
-                D3D12_BLEND_ONE, D3D12_BLEND_OP_ADD, D3D12_BLEND_SRC_ALPHA,
-                D3D12_COLOR_WRITE_ENABLE_ALL, D3D12_COMMAND_LIST_TYPE_DIRECT,
-                D3D12_COMMAND_QUEUE_DESC, D3D12_COMPARISON_FUNC_ALWAYS,
-                D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_CULL_MODE_NONE, D3D12_DEFAULT_DEPTH_BIAS,
-                D3D12_DEFAULT_DEPTH_BIAS_CLAMP, D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
-                D3D12_DEFAULT_SLOPE_SCALED_DEPTH_BIAS, D3D12_DEPTH_STENCILOP_DESC,
-                D3D12_DEPTH_STENCIL_DESC, D3D12_DEPTH_WRITE_MASK_ALL, D3D12_DESCRIPTOR_RANGE,
-                D3D12_DESCRIPTOR_RANGE_TYPE_SRV, D3D12_FENCE_FLAG_NONE, D3D12_FILL_MODE_SOLID,
-                D3D12_FILTER_MIN_MAG_MIP_LINEAR, D3D12_GPU_DESCRIPTOR_HANDLE,
-                D3D12_GRAPHICS_PIPELINE_STATE_DESC, D3D12_HEAP_FLAG_NONE, D3D12_HEAP_PROPERTIES,
+                D3D12SerializeRootSignature, D3D12SerializeVersionedRootSignature,
+                ID3D12CommandAllocator, ID3D12CommandQueue, ID3D12Device, ID3D12Fence,
+                ID3D12GraphicsCommandList, ID3D12PipelineState, ID3D12Resource,
+                ID3D12RootSignature, D3D12_COMMAND_LIST_TYPE_DIRECT, D3D12_COMMAND_QUEUE_DESC,
+                D3D12_COMPARISON_FUNC_ALWAYS, D3D12_CPU_DESCRIPTOR_HANDLE,
+                D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING, D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+                D3D12_DESCRIPTOR_RANGE, D3D12_DESCRIPTOR_RANGE1,
+                D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC_WHILE_SET_AT_EXECUTE,
+                D3D12_DESCRIPTOR_RANGE_TYPE_SRV, D3D12_FEATURE, D3D12_FEATURE_D3D12_OPTIONS,
+                D3D12_FEATURE_DATA_D3D12_OPTIONS, D3D12_FEATURE_DATA_ROOT_SIGNATURE,
+                D3D12_FEATURE_DATA_SHADER_MODEL, D3D12_FEATURE_ROOT_SIGNATURE,
+                D3D12_FEATURE_SHADER_MODEL, D3D12_FENCE_FLAG_NONE, D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+                D3D12_GPU_DESCRIPTOR_HANDLE, D3D12_HEAP_FLAG_NONE, D3D12_HEAP_PROPERTIES,
                 D3D12_HEAP_TYPE_DEFAULT, D3D12_HEAP_TYPE_UPLOAD, D3D12_INDEX_BUFFER_VIEW,
                 D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA, D3D12_INPUT_ELEMENT_DESC,
-                D3D12_INPUT_LAYOUT_DESC, D3D12_LOGIC_OP_NOOP, D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
-                D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE, D3D12_RANGE, D3D12_RASTERIZER_DESC,
-                D3D12_RENDER_TARGET_BLEND_DESC, D3D12_RESOURCE_BARRIER, D3D12_RESOURCE_BARRIER_0,
-                D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES, D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+                D3D12_PLACED_SUBRESOURCE_FOOTPRINT, D3D12_RANGE, D3D12_RESOURCE_BARRIER,
+                D3D12_RESOURCE_BARRIER_0, D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+                D3D12_RESOURCE_BARRIER_TYPE_TRANSITION, D3D12_RESOURCE_BINDING_TIER_3,
                 D3D12_RESOURCE_DESC, D3D12_RESOURCE_DIMENSION_BUFFER,
                 D3D12_RESOURCE_DIMENSION_TEXTURE2D, D3D12_RESOURCE_STATE_COPY_DEST,
                 D3D12_RESOURCE_STATE_GENERIC_READ, D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
                 D3D12_RESOURCE_TRANSITION_BARRIER, D3D12_ROOT_CONSTANTS,
-                D3D12_ROOT_DESCRIPTOR_TABLE, D3D12_ROOT_PARAMETER, D3D12_ROOT_PARAMETER_0,
+                D3D12_ROOT_DESCRIPTOR_TABLE, D3D12_ROOT_DESCRIPTOR_TABLE1, D3D12_ROOT_PARAMETER,
+                D3D12_ROOT_PARAMETER1, D3D12_ROOT_PARAMETER1_0, D3D12_ROOT_PARAMETER_0,
                 D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
                 D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE, D3D12_ROOT_SIGNATURE_DESC,
+                D3D12_ROOT_SIGNATURE_DESC1,
                 D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+                D3D12_ROOT_SIGNATURE_FLAG_CBV_SRV_UAV_HEAP_DIRECTLY_INDEXED,
                 D3D12_ROOT_SIGNATURE_FLAG_DENY_DOMAIN_SHADER_ROOT_ACCESS,
                 D3D12_ROOT_SIGNATURE_FLAG_DENY_GEOMETRY_SHADER_ROOT_ACCESS,
-                D3D12_ROOT_SIGNATURE_FLAG_DENY_HULL_SHADER_ROOT_ACCESS, D3D12_SHADER_BYTECODE,
+                D3D12_ROOT_SIGNATURE_FLAG_DENY_HULL_SHADER_ROOT_ACCESS,
                 D3D12_SHADER_RESOURCE_VIEW_DESC, D3D12_SHADER_RESOURCE_VIEW_DESC_0,
                 D3D12_SHADER_VISIBILITY_PIXEL, D3D12_SHADER_VISIBILITY_VERTEX,
                 D3D12_SRV_DIMENSION_TEXTURE2D, D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
-                D3D12_STATIC_SAMPLER_DESC, D3D12_STENCIL_OP_KEEP, D3D12_SUBRESOURCE_FOOTPRINT,
-                D3D12_TEX2D_SRV, D3D12_TEXTURE_ADDRESS_MODE_WRAP, D3D12_TEXTURE_COPY_LOCATION,
-                D3D12_TEXTURE_COPY_LOCATION_0, D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
-                D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX, D3D12_TEXTURE_DATA_PITCH_ALIGNMENT,
-                D3D12_TEXTURE_LAYOUT_ROW_MAJOR, D3D12_VERTEX_BUFFER_VIEW, D3D12_VIEWPORT,
-                D3D_ROOT_SIGNATURE_VERSION_1,
+                D3D12_STATIC_SAMPLER_DESC, D3D12_TEX2D_SRV, D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+                D3D12_TEXTURE_COPY_LOCATION, D3D12_TEXTURE_COPY_LOCATION_0,
+                D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+                D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX, D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+                D3D12_VERSIONED_ROOT_SIGNATURE_DESC, D3D12_VERSIONED_ROOT_SIGNATURE_DESC_0,
+                D3D12_VERTEX_BUFFER_VIEW, D3D12_VIEWPORT, D3D_ROOT_SIGNATURE_VERSION,
+                D3D_ROOT_SIGNATURE_VERSION_1_0, D3D_ROOT_SIGNATURE_VERSION_1_1,
+                D3D_SHADER_MODEL_6_6,
             },
             Dxgi::Common::{
                 DXGI_FORMAT, DXGI_FORMAT_R16_UINT, DXGI_FORMAT_R32G32_FLOAT, DXGI_FORMAT_R32_UINT,
                 DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC,
             },
+            Dxgi::{DXGI_ERROR_INVALID_CALL, DXGI_ERROR_UNSUPPORTED, DXGI_ERROR_WAS_STILL_DRAWING},
         },
         System::Threading::{CreateEventA, WaitForSingleObject, INFINITE},
     },
 };
 
+mod dxc;
+mod fxc;
+
+mod shader_compiler;
+use shader_compiler::{CustomShaders, ShaderStage};
+pub use shader_compiler::{
+    PrecompiledShaders, ShaderCompileOptions, ShaderCompiler, ShaderId, ShaderIncludeHandler,
+    ShaderReloadError, ShaderSource,
+};
+
+mod gpu_timer;
+use gpu_timer::GpuTimer;
+
+mod deletion_queue;
+use deletion_queue::DeletionQueue;
+
+mod draw_callbacks;
+pub use draw_callbacks::DrawCallbackContext;
+use draw_callbacks::DrawCallbacks;
+
+mod viewport_images;
+pub use viewport_images::ViewportImage;
+use viewport_images::{create_viewport_target, ViewportImages, ViewportTarget};
+
+mod render_pass;
+use render_pass::begin_render_pass;
+pub use render_pass::{RenderPass, RenderPassLoadOp};
+
+#[cfg(feature = "pix")]
+mod pix;
+
+mod pipeline_cache;
+pub use pipeline_cache::{BlendMode, PipelineLibrarySource, RenderTargetDesc};
+use pipeline_cache::{PipelineCache, PipelineLibrary};
+
+mod texture_views;
+use texture_views::{create_texture_view_srv, TextureViews, ARRAY_SLICE_PIXEL_SHADER};
+pub use texture_views::{ComponentMapping, ComponentSource, TextureViewDesc};
+
+mod texture_validation;
+pub use texture_validation::InvalidTextureId;
+use texture_validation::{PlaceholderDescriptor, TextureValidation};
+
+mod formats;
+
+mod texture_inspector;
+use texture_inspector::{InspectableTexture, TextureInspector};
+
 pub struct Renderer {
     device: ID3D12Device,
     rtv_format: DXGI_FORMAT,
@@ -77,24 +128,160 @@ pub struct Renderer {
     font_srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
     num_frames_in_flight: usize,
     frame_index: usize,
+    frame_fence: FrameFence,
     device_objects: Option<DeviceObjects>,
+    last_frame_stats: FrameStats,
+    timestamp_frequency: Option<u64>,
+    buffer_policy: BufferPolicy,
+    texture_validation: TextureValidation,
+    device_changed_callback: Option<DeviceChangedCallback>,
+    pipeline_library: Option<PipelineLibrarySource>,
+    bindless_textures: Option<BindlessTextures>,
+    shader_compile_options: ShaderCompileOptions,
+    custom_shaders: CustomShaders,
+    texture_views: TextureViews,
+    texture_inspector: TextureInspector,
+    deletion_queue: DeletionQueue,
+    draw_callbacks: DrawCallbacks,
+    viewport_images: ViewportImages,
+}
+
+// In bindless mode texture ids are indices into the shader visible CBV/SRV/UAV
+// heap that starts at heap_start.
+#[derive(Clone, Copy)]
+struct BindlessTextures {
+    heap_start: D3D12_GPU_DESCRIPTOR_HANDLE,
+    descriptor_size: u64,
 }
 
+type DeviceChangedCallback = Box<dyn FnMut(&mut Renderer) -> Result<()> + Send>;
+
 struct DeviceObjects {
     root_signature: ID3D12RootSignature,
-    pipeline_state: ID3D12PipelineState,
-    _font_texture: ID3D12Resource, // keep reference alive
+    pipeline_cache: PipelineCache,
+    font_texture: ID3D12Resource,
     frame_resources: Vec<RenderBuffers>,
+    current_frame_resources: usize,
+    gpu_timer: Option<GpuTimer>,
+    placeholder_texture: Option<ID3D12Resource>,
+}
+
+// How many times num_frames_in_flight frame resources may be allocated when the
+// GPU falls behind, before the renderer waits for it instead.
+const MAX_FRAME_RESOURCES_FACTOR: usize = 2;
+
+// Tracks when the GPU has finished with the frames recorded by the renderer.
+// Every frame recorded since the last call to Renderer::signal completes when
+// the fence reaches next_value.
+struct FrameFence {
+    fence: ID3D12Fence,
+    next_value: u64,
+    // Until the app first calls Renderer::signal there is nothing to wait on,
+    // so frame resources are reused every num_frames_in_flight frames.
+    signaled: bool,
+    // Whether a frame has been recorded since the last signal
+    unsignaled_frames: bool,
+}
+
+// The texture id used for descriptors outside the heap, which is never valid.
+const INVALID_BINDLESS_TEXTURE_ID: usize = u32::MAX as usize;
+
+impl BindlessTextures {
+    // None if the descriptor isn't in the heap.
+    fn index(&self, srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE) -> Option<u32> {
+        let offset = srv_gpu_desc_handle.ptr.checked_sub(self.heap_start.ptr)?;
+        u32::try_from(offset / self.descriptor_size).ok()
+    }
+}
+
+// The texture id of a descriptor with or without bindless textures (see
+// Renderer::texture_id).
+fn texture_id_for(
+    bindless_textures: Option<BindlessTextures>,
+    srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
+) -> TextureId {
+    match bindless_textures {
+        Some(bindless_textures) => TextureId::new(
+            bindless_textures
+                .index(srv_gpu_desc_handle)
+                .map_or(INVALID_BINDLESS_TEXTURE_ID, |index| index as usize),
+        ),
+        None => TextureId::new(srv_gpu_desc_handle.ptr as usize),
+    }
+}
+
+impl FrameFence {
+    fn new(device: &ID3D12Device) -> Result<Self> {
+        let fence: ID3D12Fence = unsafe { device.CreateFence(0, D3D12_FENCE_FLAG_NONE) }?;
+        unsafe { fence.SetName(h!("imgui frame fence")) }?;
+
+        Ok(FrameFence {
+            fence,
+            next_value: 1,
+            signaled: false,
+            unsignaled_frames: false,
+        })
+    }
+
+    // Whether the GPU has finished with work recorded in frame
+    // last_used_frame, which completes when the fence reaches fence_value.
+    fn has_completed(
+        &self,
+        frame_index: usize,
+        num_frames_in_flight: usize,
+        last_used_frame: usize,
+        fence_value: u64,
+    ) -> bool {
+        if self.signaled {
+            fence_value <= unsafe { self.fence.GetCompletedValue() }
+        } else {
+            // A frame's resources are reused num_frames_in_flight frames
+            // later, so by then the GPU must have finished with anything that
+            // frame used.
+            frame_index.wrapping_sub(last_used_frame) >= num_frames_in_flight
+        }
+    }
+
+    // Blocks until the GPU has finished the frames submitted with
+    // Renderer::signal.  Frames recorded since the last signal can't be waited
+    // for, and with no signal at all nothing can.
+    fn wait_for_submitted_frames(&self) -> Result<()> {
+        if self.signaled {
+            unsafe { wait_for_fence(&self.fence, self.next_value - 1) }?;
+        }
+
+        Ok(())
+    }
+
+    // Blocks until the GPU has finished every frame the renderer has recorded.
+    // That can only be waited for once they've all been signaled, so this
+    // fails otherwise.
+    fn wait_for_recorded_frames(&self) -> Result<()> {
+        if self.unsignaled_frames {
+            return Err(DXGI_ERROR_INVALID_CALL.into());
+        }
+
+        self.wait_for_submitted_frames()
+    }
 }
 
 #[derive(Default)]
 struct RenderBuffers {
-    index_buffer: Option<ID3D12Resource>,
-    vertex_buffer: Option<ID3D12Resource>,
-    index_buffer_size: usize,
-    vertex_buffer_size: usize,
-    vbcount: usize,
-    ibcount: usize,
+    vertex_buffer: UploadBuffer,
+    index_buffer: UploadBuffer,
+    // The frame fence value at which the GPU will have finished with these
+    // buffers.
+    fence_value: u64,
+}
+
+// An upload heap buffer holding `capacity` elements.
+#[derive(Default)]
+struct UploadBuffer {
+    resource: Option<ID3D12Resource>,
+    capacity: usize,
+    allocation_count: usize,
+    oversized_frames: usize,
+    oversized_peak: usize,
 }
 
 impl Renderer {
@@ -116,6 +303,8 @@ impl Renderer {
             .backend_flags
             .insert(BackendFlags::RENDERER_HAS_VTX_OFFSET);
 
+        let frame_fence = FrameFence::new(&device)?;
+
         Ok(Renderer {
             device,
             rtv_format,
@@ -123,24 +312,303 @@ impl Renderer {
             font_srv_gpu_desc_handle,
             num_frames_in_flight,
             frame_index: usize::MAX,
+            frame_fence,
             device_objects: None,
+            last_frame_stats: FrameStats::default(),
+            timestamp_frequency: None,
+            buffer_policy: BufferPolicy::default(),
+            texture_validation: TextureValidation::default(),
+            device_changed_callback: None,
+            pipeline_library: None,
+            bindless_textures: None,
+            shader_compile_options: ShaderCompileOptions::default(),
+            custom_shaders: CustomShaders::default(),
+            texture_views: TextureViews::default(),
+            texture_inspector: TextureInspector::default(),
+            deletion_queue: DeletionQueue::default(),
+            draw_callbacks: DrawCallbacks::default(),
+            viewport_images: ViewportImages::default(),
         })
     }
 
+    pub fn device(&self) -> &ID3D12Device {
+        &self.device
+    }
+
+    /// Returns the optional device features the renderer uses on this device.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::query(&self.device)
+    }
+
+    /// Moves the renderer to a new device, for example after the old one was
+    /// removed.  This waits for the frames submitted with `signal`, and the app
+    /// must make sure the GPU has finished any others, then drops the device
+    /// objects and recreates them on `device`, with the font texture's SRV
+    /// written to the given descriptor.
+    ///
+    /// Everything that referred to the old device or its descriptors is reset,
+    /// so afterwards the app must redo whichever of these it used:
+    ///
+    /// - `enable_gpu_timing`, with a queue on the new device
+    /// - `enable_texture_validation`, `register_texture_id` and
+    ///   `register_texture_descriptor_range`
+    /// - `enable_bindless_textures`
+    /// - `set_texture_view_descriptors` and `register_texture_view`
+    /// - `set_texture_pixel_shader`
+    ///
+    /// Registered pixel shaders, the shader compile options, the buffer policy
+    /// and the pipeline library are kept.  The callback set with
+    /// `set_device_changed_callback` is called last, and is the place to
+    /// re-upload the app's textures and redo the above.
+    ///
+    /// If waiting for the frames, creating the new fence or recompiling the
+    /// pixel shaders fails, the renderer is left unchanged on the old device.
+    /// If only creating the device objects fails, it's on the new device with
+    /// everything above reset, and `new_frame` tries to create them again.
+    pub fn set_device(
+        &mut self,
+        context: &mut Context,
+        device: ID3D12Device,
+        font_srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+        font_srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
+    ) -> Result<()> {
+        // Everything that can fail is done before the renderer is changed.
+        // Releasing objects the GPU has finished with changes nothing the app
+        // can see.
+        let frame_fence = FrameFence::new(&device)?;
+        self.deletion_queue.flush(&self.frame_fence)?;
+        if self.bindless_textures.is_some() {
+            self.custom_shaders
+                .recompile(&self.shader_compile_options, false)?;
+        }
+
+        self.invalidate_device_objects(context);
+        self.frame_fence = frame_fence;
+        self.bindless_textures = None;
+        self.device = device;
+        self.font_srv_cpu_desc_handle = font_srv_cpu_desc_handle;
+        self.font_srv_gpu_desc_handle = font_srv_gpu_desc_handle;
+        self.frame_index = usize::MAX;
+        self.timestamp_frequency = None;
+        self.texture_validation = TextureValidation::default();
+        self.custom_shaders.clear_texture_shaders();
+        let resources = self.texture_views.clear();
+        self.deletion_queue.retire(resources);
+        self.texture_inspector.device_changed();
+        self.viewport_images = ViewportImages::default();
+
+        self.create_device_objects(context)?;
+
+        if let Some(mut callback) = self.device_changed_callback.take() {
+            let result = callback(self);
+            self.device_changed_callback.get_or_insert(callback);
+            result?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets a callback that's called at the end of `set_device`, once the
+    /// renderer's own device objects have been recreated.
+    pub fn set_device_changed_callback(
+        &mut self,
+        callback: impl FnMut(&mut Renderer) -> Result<()> + Send + 'static,
+    ) {
+        self.device_changed_callback = Some(Box::new(callback));
+    }
+
     pub fn new_frame(&mut self, context: &mut Context) -> Result<()> {
+        self.draw_callbacks.clear();
+        for target in self.viewport_images.begin_frame() {
+            self.retire_viewport_target(target);
+        }
+
         if self.device_objects.is_none() {
             self.create_device_objects(context)?;
         }
 
+        if cfg!(debug_assertions) {
+            self.reload_modified_shaders();
+        }
+
+        if let Some(device_objects) = self.device_objects.as_mut() {
+            match self.timestamp_frequency {
+                Some(timestamp_frequency) if device_objects.gpu_timer.is_none() => {
+                    device_objects.gpu_timer = Some(GpuTimer::new(
+                        &self.device,
+                        timestamp_frequency,
+                        self.num_frames_in_flight * MAX_FRAME_RESOURCES_FACTOR,
+                    )?);
+                }
+                None => {
+                    if let Some(gpu_timer) = device_objects.gpu_timer.take() {
+                        self.deletion_queue.retire(gpu_timer);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        self.update_placeholder_texture()
+    }
+
+    // Creates or releases the placeholder texture to match whether texture
+    // validation is enabled.  Does nothing without device objects.
+    fn update_placeholder_texture(&mut self) -> Result<()> {
+        let Some(device_objects) = self.device_objects.as_mut() else {
+            return Ok(());
+        };
+
+        match self.texture_validation.placeholder {
+            Some(placeholder) if device_objects.placeholder_texture.is_none() => {
+                device_objects.placeholder_texture = Some(
+                    DeviceObjects::create_placeholder_texture(&self.device, placeholder)?,
+                );
+            }
+            None => {
+                if let Some(texture) = device_objects.placeholder_texture.take() {
+                    self.deletion_queue.retire(texture);
+                }
+            }
+            _ => (),
+        }
+
         Ok(())
     }
 
+    /// Starts measuring how long the GPU spends executing the commands recorded
+    /// by `render_draw_data`.  `command_queue` must be the queue that those
+    /// commands are executed on.  Timing begins with the next call to
+    /// `new_frame`.
+    pub fn enable_gpu_timing(&mut self, command_queue: &ID3D12CommandQueue) -> Result<()> {
+        self.timestamp_frequency = Some(unsafe { command_queue.GetTimestampFrequency() }?);
+        Ok(())
+    }
+
+    /// Stops measuring GPU time.  The timer is released by the next
+    /// `new_frame`, and `gpu_time_ms` returns None from then on.
+    pub fn disable_gpu_timing(&mut self) {
+        self.timestamp_frequency = None;
+    }
+
+    /// Enables validation of the texture ids used by draw commands.  Ids that
+    /// haven't been registered with `register_texture_id` or
+    /// `register_texture_descriptor_range` are reported by
+    /// `texture_validation_errors` and drawn with a magenta placeholder texture
+    /// instead.  The font texture is always valid.
+    ///
+    /// The placeholder texture's SRV is written to the given descriptor, right
+    /// away if the device objects exist and otherwise when they're created.
+    pub fn enable_texture_validation(
+        &mut self,
+        placeholder_srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+        placeholder_srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
+    ) -> Result<()> {
+        let previous = self
+            .texture_validation
+            .placeholder
+            .replace(PlaceholderDescriptor {
+                cpu_desc_handle: placeholder_srv_cpu_desc_handle,
+                gpu_desc_handle: placeholder_srv_gpu_desc_handle,
+                texture_id: self.texture_id(placeholder_srv_gpu_desc_handle),
+            });
+
+        // Make sure the placeholder is recreated in a new descriptor.  Frames
+        // in flight may be reading the current one, so it's left alone.
+        let unchanged = previous
+            .is_some_and(|previous| previous.cpu_desc_handle == placeholder_srv_cpu_desc_handle);
+        if unchanged {
+            return Ok(());
+        }
+        if let Some(texture) = self
+            .device_objects
+            .as_mut()
+            .and_then(|device_objects| device_objects.placeholder_texture.take())
+        {
+            self.deletion_queue.retire(texture);
+        }
+
+        self.update_placeholder_texture()
+    }
+
+    /// Stops validating texture ids and forgets the errors already reported.
+    /// Registered ids are kept for when validation is enabled again.
+    pub fn disable_texture_validation(&mut self) {
+        self.texture_validation.placeholder = None;
+        self.texture_validation.invalid_texture_ids.clear();
+    }
+
+    /// Marks a texture id as valid for texture validation.
+    pub fn register_texture_id(&mut self, texture_id: TextureId) {
+        self.texture_validation.register_texture_id(texture_id);
+    }
+
+    pub fn unregister_texture_id(&mut self, texture_id: TextureId) {
+        self.texture_validation.unregister_texture_id(texture_id);
+    }
+
+    /// Marks the texture ids of `count` SRV descriptors starting at `start` as
+    /// valid for texture validation.  The ids depend on whether bindless
+    /// textures are enabled, so ranges should be registered after choosing.
+    /// With bindless textures, a range that starts before the heap is ignored.
+    pub fn register_texture_descriptor_range(
+        &mut self,
+        start: D3D12_GPU_DESCRIPTOR_HANDLE,
+        count: u32,
+    ) {
+        let (start, increment) = match self.bindless_textures {
+            Some(bindless_textures) => match bindless_textures.index(start) {
+                Some(index) => (index as u64, 1),
+                None => return,
+            },
+            None => (start.ptr, self.descriptor_size()),
+        };
+        self.texture_validation
+            .register_id_range(start, count, increment);
+    }
+
+    /// Returns the draw commands in the most recently rendered frame that used
+    /// invalid texture ids.  Always empty if texture validation isn't enabled.
+    pub fn texture_validation_errors(&self) -> &[InvalidTextureId] {
+        &self.texture_validation.invalid_texture_ids
+    }
+
+    /// Returns the GPU time, in milliseconds, of the most recent UI pass that
+    /// has been measured.  Results only become available once the GPU has
+    /// finished with a frame, so this lags `num_frames_in_flight` frames
+    /// behind.
+    pub fn gpu_time_ms(&self) -> Option<f64> {
+        self.device_objects
+            .as_ref()
+            .and_then(|device_objects| device_objects.gpu_timer.as_ref())
+            .and_then(GpuTimer::last_time_ms)
+    }
+
+    /// Drops the device objects, which are recreated by the next `new_frame`.
+    /// They're kept alive until the frames that may still be using them have
+    /// finished.
     pub fn invalidate_device_objects(&mut self, context: &mut Context) {
         context.fonts().tex_id = TextureId::new(0);
-        self.device_objects = None;
+        if let Some(device_objects) = self.device_objects.take() {
+            self.deletion_queue.retire(device_objects);
+        }
     }
 
     pub fn create_device_objects(&mut self, context: &mut Context) -> Result<()> {
+        self.create_device_objects_for(context, self.bindless_textures)
+    }
+
+    // Creates the device objects for `bindless_textures`, which the caller
+    // makes the renderer's mode once this has succeeded.
+    fn create_device_objects_for(
+        &mut self,
+        context: &mut Context,
+        bindless_textures: Option<BindlessTextures>,
+    ) -> Result<()> {
+        // The new font texture's SRV is written to the font descriptor, which
+        // frames in flight may still be reading
+        self.frame_fence.wait_for_submitted_frames()?;
+
         if self.device_objects.is_some() {
             self.invalidate_device_objects(context);
         }
@@ -148,242 +616,1006 @@ impl Renderer {
         self.device_objects = Some(DeviceObjects::new(
             context,
             &self.device,
-            self.rtv_format,
-            self.font_srv_cpu_desc_handle,
-            self.font_srv_gpu_desc_handle,
-            self.num_frames_in_flight,
+            &DeviceObjectsSettings {
+                rtv_format: self.rtv_format,
+                font_srv_cpu_desc_handle: self.font_srv_cpu_desc_handle,
+                font_texture_id: texture_id_for(bindless_textures, self.font_srv_gpu_desc_handle),
+                num_frames_in_flight: self.num_frames_in_flight,
+                pipeline_library: self.pipeline_library.as_ref(),
+                bindless_textures: bindless_textures.is_some(),
+                shader_compile_options: &self.shader_compile_options,
+            },
         )?);
 
-        Ok(())
+        self.update_placeholder_texture()
     }
-}
 
-impl DeviceObjects {
-    fn new(
-        context: &mut Context,
-        device: &ID3D12Device,
-        rtv_format: DXGI_FORMAT,
-        font_srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
-        font_srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
-        num_frames_in_flight: usize,
-    ) -> Result<Self> {
-        let root_signature = Self::create_root_signature(device)?;
-        let pipeline_state = Self::create_pipeline_state(device, rtv_format, &root_signature)?;
+    /// Loads the renderer's pipeline states from a pipeline library, rather
+    /// than compiling them, and stores any that are missing.  If the library
+    /// was written by a different driver or adapter it is silently replaced
+    /// with an empty one.  Does nothing if the device doesn't support
+    /// pipeline libraries.
+    pub fn enable_pipeline_library(&mut self, source: PipelineLibrarySource) {
+        if let Some(device_objects) = self.device_objects.as_mut() {
+            device_objects
+                .pipeline_cache
+                .set_library(PipelineLibrary::new(&self.device, &source));
+        }
 
-        let font_texture = Self::create_fonts_texture(
-            device,
-            context,
-            font_srv_cpu_desc_handle,
-            font_srv_gpu_desc_handle,
-        )?;
+        self.pipeline_library = Some(source);
+    }
 
-        let mut frame_resources: Vec<RenderBuffers> = Vec::new();
-        frame_resources.resize_with(num_frames_in_flight, RenderBuffers::default);
+    /// Stops using the pipeline library.  Pipeline states that were loaded from
+    /// it are kept, and new ones are compiled.
+    pub fn disable_pipeline_library(&mut self) {
+        if let Some(device_objects) = self.device_objects.as_mut() {
+            device_objects.pipeline_cache.set_library(None);
+        }
 
-        Ok(DeviceObjects {
-            root_signature,
-            pipeline_state,
-            _font_texture: font_texture,
-            frame_resources,
-        })
+        self.pipeline_library = None;
     }
 
-    fn create_pipeline_state(
-        device: &ID3D12Device,
-        rtv_format: DXGI_FORMAT,
-        root_signature: &ID3D12RootSignature,
-    ) -> Result<ID3D12PipelineState> {
-        let (vertex_shader, input_layout) = Self::create_vertex_shader()?;
-        let pixel_shader = Self::create_pixel_shader()?;
-
-        let shader_bytecode = |shader: &ID3DBlob| unsafe {
-            D3D12_SHADER_BYTECODE {
-                pShaderBytecode: shader.GetBufferPointer(),
-                BytecodeLength: shader.GetBufferSize(),
-            }
-        };
-
-        let default_stencilop = D3D12_DEPTH_STENCILOP_DESC {
-            StencilFailOp: D3D12_STENCIL_OP_KEEP,
-            StencilDepthFailOp: D3D12_STENCIL_OP_KEEP,
-            StencilPassOp: D3D12_STENCIL_OP_KEEP,
-            StencilFunc: D3D12_COMPARISON_FUNC_ALWAYS,
-        };
-
-        let mut desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
-            pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
-            VS: shader_bytecode(&vertex_shader),
-            PS: shader_bytecode(&pixel_shader),
-            BlendState: D3D12_BLEND_DESC {
-                AlphaToCoverageEnable: false.into(),
-                IndependentBlendEnable: false.into(),
-                RenderTarget: [
-                    D3D12_RENDER_TARGET_BLEND_DESC {
-                        BlendEnable: true.into(),
-                        LogicOpEnable: false.into(),
-                        SrcBlend: D3D12_BLEND_SRC_ALPHA,
-                        DestBlend: D3D12_BLEND_INV_SRC_ALPHA,
-                        BlendOp: D3D12_BLEND_OP_ADD,
-                        SrcBlendAlpha: D3D12_BLEND_ONE,
-                        DestBlendAlpha: D3D12_BLEND_INV_SRC_ALPHA,
-                        BlendOpAlpha: D3D12_BLEND_OP_ADD,
-                        LogicOp: D3D12_LOGIC_OP_NOOP,
-                        RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
-                    },
-                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                ],
-            },
-            SampleMask: u32::MAX,
-            RasterizerState: D3D12_RASTERIZER_DESC {
-                FillMode: D3D12_FILL_MODE_SOLID,
-                CullMode: D3D12_CULL_MODE_NONE,
-                DepthBias: D3D12_DEFAULT_DEPTH_BIAS,
-                DepthBiasClamp: D3D12_DEFAULT_DEPTH_BIAS_CLAMP,
-                SlopeScaledDepthBias: D3D12_DEFAULT_SLOPE_SCALED_DEPTH_BIAS,
-                DepthClipEnable: TRUE,
-                ..Default::default()
-            },
-            DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
-                DepthEnable: FALSE,
-                DepthWriteMask: D3D12_DEPTH_WRITE_MASK_ALL,
-                DepthFunc: D3D12_COMPARISON_FUNC_ALWAYS,
-                StencilEnable: FALSE,
-                FrontFace: default_stencilop,
-                BackFace: default_stencilop,
-                ..Default::default()
-            },
-            InputLayout: D3D12_INPUT_LAYOUT_DESC {
-                pInputElementDescs: input_layout.as_ptr(),
-                NumElements: input_layout.len() as u32,
-            },
-            PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
-            NumRenderTargets: 1,
-            SampleDesc: DXGI_SAMPLE_DESC {
-                Count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
-        desc.RTVFormats[0] = rtv_format;
+    /// Returns the contents of the pipeline library, to be passed back as
+    /// `PipelineLibrarySource::Memory` in a later run.  Returns `None` if
+    /// there is no pipeline library.
+    pub fn serialize_pipeline_library(&self) -> Result<Option<Vec<u8>>> {
+        self.device_objects
+            .as_ref()
+            .and_then(|device_objects| device_objects.pipeline_cache.library())
+            .map(PipelineLibrary::serialize)
+            .transpose()
+    }
 
-        unsafe { device.CreateGraphicsPipelineState(&desc) }
+    /// Writes the pipeline library to the file it was loaded from.  Does
+    /// nothing unless the library was enabled with
+    /// `PipelineLibrarySource::File`.
+    pub fn save_pipeline_library(&self) -> Result<()> {
+        if let Some(PipelineLibrarySource::File(path)) = self.pipeline_library.as_ref() {
+            if let Some(data) = self.serialize_pipeline_library()? {
+                std::fs::write(path, data)?;
+            }
+        }
+
+        Ok(())
     }
 
-    fn create_root_signature(device: &ID3D12Device) -> Result<ID3D12RootSignature> {
-        let desc_range = D3D12_DESCRIPTOR_RANGE {
-            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
-            NumDescriptors: 1,
-            ..Default::default()
-        };
+    /// Rebuilds the font atlas texture without recreating the rest of the
+    /// device objects.  Call this after adding fonts or changing font sizes.
+    ///
+    /// Frames in flight may still be reading the font descriptor, so before
+    /// the new texture's SRV is written to it this waits for every frame
+    /// recorded by `render_draw_data` to finish.  That needs each of them to
+    /// have been followed by `signal`: call this between frames, after
+    /// signaling.  If a frame hasn't been signaled this fails with
+    /// `DXGI_ERROR_INVALID_CALL` and changes nothing.  Before the first frame
+    /// it can be called at any time.
+    pub fn rebuild_fonts(&mut self, context: &mut Context) -> Result<()> {
+        let font_texture_id = self.texture_id(self.font_srv_gpu_desc_handle);
+
+        // If there are no device objects then the fonts will be built when
+        // they're created.
+        if let Some(device_objects) = self.device_objects.as_mut() {
+            self.frame_fence.wait_for_recorded_frames()?;
+            let old_font_texture = device_objects.rebuild_fonts(
+                context,
+                &self.device,
+                self.font_srv_cpu_desc_handle,
+                font_texture_id,
+            )?;
+            self.deletion_queue.retire(old_font_texture);
+        }
 
-        let param = [
-            D3D12_ROOT_PARAMETER {
-                ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
-                Anonymous: D3D12_ROOT_PARAMETER_0 {
-                    Constants: D3D12_ROOT_CONSTANTS {
-                        ShaderRegister: 0,
-                        RegisterSpace: 0,
-                        Num32BitValues: 16,
-                    },
-                },
-                ShaderVisibility: D3D12_SHADER_VISIBILITY_VERTEX,
-            },
-            D3D12_ROOT_PARAMETER {
-                ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
-                Anonymous: D3D12_ROOT_PARAMETER_0 {
-                    DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
-                        NumDescriptorRanges: 1,
-                        pDescriptorRanges: &desc_range,
-                    },
-                },
-                ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
-            },
-        ];
+        Ok(())
+    }
 
-        // Bilinear sampling is required by default. Set 'io.Fonts->Flags |=
-        // ImFontAtlasFlags_NoBakedLines' or 'style.AntiAliasedLinesUseTex =
-        // false' to allow point/nearest sampling.
-        let static_sampler = D3D12_STATIC_SAMPLER_DESC {
-            Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
-            AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-            AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-            AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
-            MipLODBias: 0.0,
-            MaxAnisotropy: 0,
-            ComparisonFunc: D3D12_COMPARISON_FUNC_ALWAYS,
-            BorderColor: D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
-            MinLOD: 0.0,
-            MaxLOD: 0.0,
-            ShaderRegister: 0,
-            RegisterSpace: 0,
-            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
-        };
+    /// Signals the renderer's fence on `command_queue`.  Call this after
+    /// executing the command lists passed to `render_draw_data`.
+    ///
+    /// Once this has been called, the renderer only reuses a frame's vertex
+    /// and index buffers after the GPU has finished with them, rather than
+    /// assuming that it has after `num_frames_in_flight` frames.  If the GPU
+    /// is still using every frame's buffers, more are allocated, up to twice
+    /// `num_frames_in_flight`, after which the renderer waits for the oldest.
+    /// Keep calling this every frame: if all of those buffers belong to frames
+    /// that haven't been signaled, `render_draw_data` fails with
+    /// `DXGI_ERROR_WAS_STILL_DRAWING` and records nothing.
+    pub fn signal(&mut self, command_queue: &ID3D12CommandQueue) -> Result<()> {
+        unsafe { command_queue.Signal(&self.frame_fence.fence, self.frame_fence.next_value) }?;
+        self.frame_fence.next_value += 1;
+        self.frame_fence.signaled = true;
+        self.frame_fence.unsignaled_frames = false;
+        Ok(())
+    }
+
+    /// Switches to bindless textures.  Texture ids become indices into the
+    /// shader visible CBV/SRV/UAV heap starting at `heap_start`, which must be
+    /// the heap bound when `render_draw_data` is called, and the pixel shader
+    /// reads textures through `ResourceDescriptorHeap` rather than a
+    /// descriptor table.  The font texture's id becomes its descriptor's
+    /// index.
+    ///
+    /// Requires shader model 6.6 and resource binding tier 3 (see
+    /// `capabilities`), and dxcompiler.dll to compile the shaders.  The
+    /// device objects are recreated.  Fails with `E_INVALIDARG` if the font
+    /// descriptor comes before `heap_start`.  If the shaders or device
+    /// objects can't be created, the texture ids don't change.
+    pub fn enable_bindless_textures(
+        &mut self,
+        context: &mut Context,
+        heap_start: D3D12_GPU_DESCRIPTOR_HANDLE,
+    ) -> Result<()> {
+        if !self.capabilities().bindless_textures {
+            return Err(DXGI_ERROR_UNSUPPORTED.into());
+        }
 
-        let desc = D3D12_ROOT_SIGNATURE_DESC {
-            NumParameters: param.len() as u32,
-            pParameters: param.as_ptr(),
-            NumStaticSamplers: 1,
-            pStaticSamplers: &static_sampler,
-            Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT
-                | D3D12_ROOT_SIGNATURE_FLAG_DENY_HULL_SHADER_ROOT_ACCESS
-                | D3D12_ROOT_SIGNATURE_FLAG_DENY_DOMAIN_SHADER_ROOT_ACCESS
-                | D3D12_ROOT_SIGNATURE_FLAG_DENY_GEOMETRY_SHADER_ROOT_ACCESS,
+        let bindless_textures = BindlessTextures {
+            heap_start,
+            descriptor_size: self.descriptor_size(),
         };
+        if bindless_textures
+            .index(self.font_srv_gpu_desc_handle)
+            .is_none()
+        {
+            return Err(E_INVALIDARG.into());
+        }
 
-        let mut signature = None;
-        let signature = unsafe {
-            D3D12SerializeRootSignature(&desc, D3D_ROOT_SIGNATURE_VERSION_1, &mut signature, None)
+        self.texture_ids_changed(context, Some(bindless_textures))
+    }
+
+    /// Switches back to texture ids that are GPU descriptor handles.  The
+    /// device objects are recreated and custom pixel shaders recompiled.
+    /// Apart from those of texture views, ids and ranges registered for
+    /// texture validation, and pixel shaders assigned to texture ids, refer to
+    /// the old ids and must be set again.  If the shaders or device objects
+    /// can't be created, bindless textures stay enabled.
+    pub fn disable_bindless_textures(&mut self, context: &mut Context) -> Result<()> {
+        self.texture_ids_changed(context, None)
+    }
+
+    /// Returns the texture id that refers to an SRV descriptor.  This is the
+    /// descriptor's GPU handle, or its index in the heap if bindless textures
+    /// are enabled.  With bindless textures, a descriptor outside the heap
+    /// gets an id that's never valid, which texture validation reports.
+    pub fn texture_id(&self, srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE) -> TextureId {
+        texture_id_for(self.bindless_textures, srv_gpu_desc_handle)
+    }
+
+    // Switches to `bindless_textures`.  The custom shaders and device objects
+    // are built for the new mode first, and the renderer only changes once
+    // they have been, so if either fails the old mode is kept.
+    fn texture_ids_changed(
+        &mut self,
+        context: &mut Context,
+        bindless_textures: Option<BindlessTextures>,
+    ) -> Result<()> {
+        let bytecode = self
+            .custom_shaders
+            .compile_all(&self.shader_compile_options, bindless_textures.is_some())?;
+        self.create_device_objects_for(context, bindless_textures)?;
+
+        self.bindless_textures = bindless_textures;
+        self.custom_shaders.replace_bytecode(bytecode);
+
+        // The inspector and viewport images register new views when they're
+        // next used
+        if let Some(texture_id) = self.texture_inspector.take_view() {
+            self.unregister_texture_view(texture_id);
         }
-        .map(|()| signature.unwrap())?;
+        self.release_viewport_images();
 
-        unsafe {
-            device.CreateRootSignature(
-                0,
-                std::slice::from_raw_parts(
-                    signature.GetBufferPointer() as _,
-                    signature.GetBufferSize(),
-                ),
-            )
+        if let Some(mut placeholder) = self.texture_validation.placeholder {
+            placeholder.texture_id = self.texture_id(placeholder.gpu_desc_handle);
+            self.texture_validation.placeholder = Some(placeholder);
         }
+
+        self.update_texture_view_ids();
+        Ok(())
     }
 
-    fn create_vertex_shader() -> Result<(ID3DBlob, [D3D12_INPUT_ELEMENT_DESC; 3])> {
-        let shader = compile_shader(
-            r"
-    cbuffer vertexBuffer: register(b0) {
-                float4x4 ProjectionMatrix;
-            };
-    
-            struct VS_INPUT {
-                float2 pos: POSITION;
-                float4 col: COLOR0;
-                float2 uv: TEXCOORD0;
-            };
-    
-            struct PS_INPUT {
-                float4 pos: SV_POSITION;
-                float4 col: COLOR0;
-                float2 uv: TEXCOORD0;
-            };
-    
-            PS_INPUT main(VS_INPUT input) {
-                PS_INPUT output;
+    // Texture views keep their descriptors, but the ids that refer to them
+    // change, along with the places those ids are registered.
+    fn update_texture_view_ids(&mut self) {
+        let texture_ids: Vec<TextureId> = self
+            .texture_views
+            .views
+            .iter()
+            .map(|view| self.texture_id(self.texture_views.view_gpu_handle(view)))
+            .collect();
+
+        for view in &self.texture_views.views {
+            self.texture_validation
+                .unregister_texture_id(view.texture_id);
+            self.custom_shaders
+                .set_texture_shader(view.texture_id, None);
+        }
+
+        for (view, texture_id) in self.texture_views.views.iter_mut().zip(texture_ids) {
+            view.texture_id = texture_id;
+            self.texture_validation.register_texture_id(texture_id);
+            if view.is_array {
+                self.custom_shaders
+                    .set_texture_shader(texture_id, self.texture_views.array_shader);
+            }
+        }
+    }
+
+    /// Sets aside `count` consecutive descriptors in the shader visible
+    /// CBV/SRV/UAV heap, starting at the given handles, for the SRVs created
+    /// by `register_texture_view`.  Views registered in a previous range are
+    /// forgotten, and their resources released once the frames that may have
+    /// drawn them have finished.  The renderer stops writing to the previous
+    /// range, but those frames may still read it, so the app shouldn't reuse
+    /// it until they're done.
+    pub fn set_texture_view_descriptors(
+        &mut self,
+        cpu_start: D3D12_CPU_DESCRIPTOR_HANDLE,
+        gpu_start: D3D12_GPU_DESCRIPTOR_HANDLE,
+        count: u32,
+    ) {
+        self.texture_inspector.take_view();
+        self.release_viewport_images();
+        for view in &self.texture_views.views {
+            self.texture_validation
+                .unregister_texture_id(view.texture_id);
+            self.custom_shaders
+                .set_texture_shader(view.texture_id, None);
+        }
+
+        let descriptor_size = self.descriptor_size();
+        let resources =
+            self.texture_views
+                .set_descriptors(cpu_start, gpu_start, count, descriptor_size);
+        self.deletion_queue.retire(resources);
+    }
+
+    /// Creates an SRV for `resource`, as described by `desc`, in one of the
+    /// descriptors set aside with `set_texture_view_descriptors`, and returns
+    /// a texture id that draws it.  Each call returns a distinct id, so the
+    /// same resource can be shown in several ways at once.  Single sampled 2D
+    /// textures and texture arrays are supported.
+    ///
+    /// The id is registered for texture validation.  It depends on whether
+    /// bindless textures are enabled, and is updated if that changes.
+    pub fn register_texture_view(
+        &mut self,
+        resource: &ID3D12Resource,
+        desc: &TextureViewDesc,
+    ) -> Result<TextureId> {
+        let index = self.texture_views.allocate()?;
+
+        let result = self.create_texture_view(index, resource, desc);
+        if result.is_err() {
+            self.texture_views.free(index);
+        }
+
+        result
+    }
+
+    fn create_texture_view(
+        &mut self,
+        index: u32,
+        resource: &ID3D12Resource,
+        desc: &TextureViewDesc,
+    ) -> Result<TextureId> {
+        let is_array = create_texture_view_srv(
+            &self.device,
+            resource,
+            desc,
+            self.texture_views.cpu_handle(index),
+        )?;
+        let texture_id = self.texture_id(self.texture_views.gpu_handle(index));
+
+        // The built-in pixel shader only samples Texture2Ds
+        if is_array {
+            let shader = match self.texture_views.array_shader {
+                Some(shader) => shader,
+                None => {
+                    let shader = self.register_pixel_shader(ShaderSource::Hlsl {
+                        source: ARRAY_SLICE_PIXEL_SHADER.to_string(),
+                        entry_point: "main".to_string(),
+                    })?;
+                    self.texture_views.array_shader = Some(shader);
+                    shader
+                }
+            };
+            self.custom_shaders
+                .set_texture_shader(texture_id, Some(shader));
+        }
+
+        self.texture_views
+            .insert(index, texture_id, resource.clone(), is_array);
+        self.texture_validation.register_texture_id(texture_id);
+
+        Ok(texture_id)
+    }
+
+    /// Removes a view created by `register_texture_view`.  Its descriptor is
+    /// reused, and its resource released, once the frames that may have
+    /// drawn it have finished.
+    pub fn unregister_texture_view(&mut self, texture_id: TextureId) {
+        if let Some(view) =
+            self.texture_views
+                .remove(texture_id, self.frame_index, self.frame_fence.next_value)
+        {
+            self.deletion_queue.retire(view.resource);
+            self.texture_validation.unregister_texture_id(texture_id);
+            self.custom_shaders.set_texture_shader(texture_id, None);
+        }
+    }
+
+    /// Creates a 2D texture from pixel data, waiting for the upload to
+    /// complete, and returns a texture id that draws it.  Any format the
+    /// device supports for textures can be used, including block compressed
+    /// ones.
+    ///
+    /// `data` holds `mips` mip levels one after the other, most detailed
+    /// first, each with its rows tightly packed.  For block compressed formats
+    /// a row is a row of 4x4 blocks.
+    ///
+    /// The texture is drawn through a texture view, so this takes one of the
+    /// descriptors set aside with `set_texture_view_descriptors`, and the
+    /// texture is released with `unregister_texture_view`.
+    pub fn create_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: DXGI_FORMAT,
+        mips: u32,
+        data: &[u8],
+    ) -> Result<TextureId> {
+        let texture = create_texture_resource(
+            &self.device,
+            width,
+            height,
+            format,
+            mips,
+            data,
+            h!("imgui texture"),
+        )?;
+
+        self.register_texture_view(&texture, &TextureViewDesc::default())
+    }
+
+    /// Returns a render target for the current window to show, for example a
+    /// 3D scene view.  It's sized to fill the window's remaining content
+    /// region, in pixels, and is identified by `id` from frame to frame.
+    /// Render into `rtv` before `render_draw_data` and draw `texture_id` at
+    /// `display_size` with `imgui::Image`.
+    ///
+    /// The target is kept in `D3D12_RESOURCE_STATE_RENDER_TARGET`, except
+    /// while `render_draw_data` draws the UI.  When the window's size or
+    /// `format` changes a new target is created, and the old one is released
+    /// once the frames using it have finished.  Targets that aren't asked for
+    /// during a frame are released by the next `new_frame`.
+    ///
+    /// The texture id is a texture view, so this takes one of the descriptors
+    /// set aside with `set_texture_view_descriptors`.
+    pub fn viewport_image(
+        &mut self,
+        ui: &Ui,
+        id: &str,
+        format: DXGI_FORMAT,
+    ) -> Result<ViewportImage> {
+        let available = ui.content_region_avail();
+        let display_size = [available[0].max(1.0), available[1].max(1.0)];
+        let scale = ui.io().display_framebuffer_scale;
+        let size = [
+            (display_size[0] * scale[0]).round().max(1.0) as u32,
+            (display_size[1] * scale[1]).round().max(1.0) as u32,
+        ];
+
+        if let Some(index) = self.viewport_images.position(id) {
+            let target = &mut self.viewport_images.targets[index];
+            target.used = true;
+            if target.size == size && target.format == format {
+                return Ok(target.image(display_size));
+            }
+
+            let target = self.viewport_images.targets.remove(index);
+            self.retire_viewport_target(target);
+        }
+
+        let (resource, rtv_heap) = create_viewport_target(&self.device, id, format, size)?;
+        let texture_id = self.register_texture_view(&resource, &TextureViewDesc::default())?;
+
+        let target = ViewportTarget {
+            id: id.to_string(),
+            format,
+            size,
+            resource,
+            rtv_heap,
+            texture_id,
+            used: true,
+        };
+        let image = target.image(display_size);
+        self.viewport_images.targets.push(target);
+
+        Ok(image)
+    }
+
+    // Unregisters a viewport image's texture view, and releases its resources
+    // once the frames that used them have finished.
+    fn retire_viewport_target(&mut self, target: ViewportTarget) {
+        self.unregister_texture_view(target.texture_id);
+        self.deletion_queue
+            .retire((target.resource, target.rtv_heap));
+    }
+
+    fn release_viewport_images(&mut self) {
+        for target in std::mem::take(&mut self.viewport_images.targets) {
+            self.retire_viewport_target(target);
+        }
+    }
+
+    /// Draws a window that lists the renderer's textures: the font texture,
+    /// the placeholder texture and those registered with
+    /// `register_texture_view`.  The selected texture can be zoomed with the
+    /// mouse wheel and panned by dragging, with a pixel grid, point or linear
+    /// sampling and channel toggles, and the hovered pixel's value is read
+    /// back from the GPU.  Textures are expected to be in the pixel shader
+    /// resource state while the frame is rendered.
+    ///
+    /// The inspector draws the selected mip, slice and channels through a
+    /// texture view of its own, so this needs a free descriptor from
+    /// `set_texture_view_descriptors`; without one the whole texture is drawn
+    /// as it is.  Call this between `Context::new_frame` and rendering, with
+    /// `opened` set to false to hide the window, which releases the view and
+    /// the readback buffer.
+    pub fn show_texture_inspector(&mut self, ui: &Ui, opened: &mut bool) {
+        let mut texture_inspector = std::mem::take(&mut self.texture_inspector);
+        if *opened {
+            texture_inspector.show(self, ui, opened);
+        } else {
+            texture_inspector.hide(self);
+        }
+        self.texture_inspector = texture_inspector;
+    }
+
+    /// Returns why the texture inspector's hovered pixel couldn't be read
+    /// back in the most recent frame, or `None` if it could.  The error is
+    /// also shown in the inspector window.
+    pub fn texture_inspector_readback_error(&self) -> Option<&Error> {
+        self.texture_inspector.readback_error()
+    }
+
+    // The textures shown by the inspector, other than its own view.
+    fn inspectable_textures(&self, exclude: Option<TextureId>) -> Vec<InspectableTexture> {
+        let mut textures = Vec::new();
+
+        if let Some(device_objects) = self.device_objects.as_ref() {
+            textures.push(InspectableTexture {
+                name: debug_object_name(&device_objects.font_texture)
+                    .unwrap_or_else(|| "font texture".to_string()),
+                resource: device_objects.font_texture.clone(),
+                texture_id: self.texture_id(self.font_srv_gpu_desc_handle),
+            });
+
+            if let (Some(texture), Some(placeholder)) = (
+                device_objects.placeholder_texture.as_ref(),
+                self.texture_validation.placeholder,
+            ) {
+                textures.push(InspectableTexture {
+                    name: debug_object_name(texture)
+                        .unwrap_or_else(|| "placeholder texture".to_string()),
+                    resource: texture.clone(),
+                    texture_id: placeholder.texture_id,
+                });
+            }
+        }
+
+        for view in &self.texture_views.views {
+            if Some(view.texture_id) == exclude
+                || textures
+                    .iter()
+                    .any(|texture| texture.resource == view.resource)
+            {
+                continue;
+            }
+
+            textures.push(InspectableTexture {
+                name: debug_object_name(&view.resource)
+                    .unwrap_or_else(|| format!("texture {}", view.texture_id.id())),
+                resource: view.resource.clone(),
+                texture_id: view.texture_id,
+            });
+        }
+
+        textures
+    }
+
+    /// Sets the compiler and options used for the built-in shaders and for
+    /// custom pixel shaders.  Custom shaders are recompiled and the device
+    /// objects are recreated.  If a custom shader fails to compile, the
+    /// previous options are kept.
+    pub fn set_shader_compile_options(
+        &mut self,
+        context: &mut Context,
+        options: ShaderCompileOptions,
+    ) -> Result<()> {
+        self.custom_shaders
+            .recompile(&options, self.bindless_textures.is_some())?;
+        self.shader_compile_options = options;
+        self.create_device_objects(context)
+    }
+
+    pub fn shader_compile_options(&self) -> &ShaderCompileOptions {
+        &self.shader_compile_options
+    }
+
+    /// Compiles a pixel shader that can be used instead of the built-in one
+    /// for particular textures (see `set_texture_pixel_shader`).  Compile
+    /// errors are returned in the error's message.  If the device objects
+    /// exist, a pipeline state is also created with the shader, so bytecode
+    /// the device rejects fails here.  Otherwise, or for other render targets,
+    /// textures whose shader can't be used are drawn with the built-in one and
+    /// counted in `FrameStats::pixel_shader_fallbacks`.
+    pub fn register_pixel_shader(&mut self, source: ShaderSource) -> Result<ShaderId> {
+        let bytecode = self.shader_compile_options.compile(
+            ShaderStage::Pixel,
+            &source,
+            self.bindless_textures.is_some(),
+        )?;
+        let id = self.custom_shaders.insert(source, bytecode.clone());
+
+        // Bytecode that doesn't match the renderer's root signature or vertex
+        // shader is caught here, where the app can handle it, rather than
+        // when a texture is drawn
+        if let Some(device_objects) = self.device_objects.as_mut() {
+            if let Err(error) = device_objects.pipeline_cache.get_or_create(
+                &self.device,
+                &device_objects.root_signature,
+                &RenderTargetDesc::new(self.rtv_format),
+                Some((id, &bytecode)),
+            ) {
+                self.unregister_pixel_shader(id);
+                return Err(error);
+            }
+        }
+
+        Ok(id)
+    }
+
+    /// Adds a callback to the current window's draw list.  When the draw list
+    /// is rendered, the callback is called with the command list, its clip
+    /// rectangle projected into render target space, the viewport and the
+    /// frame index.  It's skipped if its clip rectangle is empty.  Afterwards
+    /// the renderer sets the viewport, root signature and arguments, pipeline
+    /// state, vertex and index buffers, primitive topology, blend factor and
+    /// scissor rectangle again, so the callback may change those.  It must
+    /// leave the descriptor heaps and render targets as they were.
+    ///
+    /// Callbacks are kept until the next `new_frame`, and are called each
+    /// time the draw data is rendered.
+    pub fn add_draw_callback(
+        &mut self,
+        _ui: &Ui,
+        callback: impl FnMut(&DrawCallbackContext) + Send + 'static,
+    ) {
+        self.draw_callbacks.add(callback);
+    }
+
+    /// Removes a custom pixel shader.  Textures that were drawn with it go
+    /// back to the built-in pixel shader.
+    pub fn unregister_pixel_shader(&mut self, shader: ShaderId) {
+        self.custom_shaders.remove(shader);
+
+        if let Some(device_objects) = self.device_objects.as_mut() {
+            let pipeline_states = device_objects.pipeline_cache.remove_pixel_shader(shader);
+            self.deletion_queue.retire(pipeline_states);
+        }
+    }
+
+    /// Returns the custom pixel shaders whose files changed but failed to
+    /// recompile, or recompiled to bytecode a pipeline state couldn't be
+    /// created with.  In debug builds, shaders registered as
+    /// `ShaderSource::HlslFile` are recompiled by `new_frame` when their file
+    /// changes; files they include aren't watched.  Always empty in release
+    /// builds.
+    pub fn shader_reload_errors(&self) -> &[ShaderReloadError] {
+        self.custom_shaders.reload_errors()
+    }
+
+    // Pipeline states using a reloaded shader are dropped, so they're rebuilt
+    // with the new bytecode the next time they're used.  As with
+    // register_pixel_shader, one is created for the default render target
+    // first; if that fails the shader goes back to its old bytecode.
+    fn reload_modified_shaders(&mut self) {
+        let reloaded = self.custom_shaders.reload_modified(
+            &self.shader_compile_options,
+            self.bindless_textures.is_some(),
+        );
+
+        let Some(device_objects) = self.device_objects.as_mut() else {
+            return;
+        };
+
+        for (shader, previous) in reloaded {
+            let pipeline_states = device_objects.pipeline_cache.remove_pixel_shader(shader);
+            self.deletion_queue.retire(pipeline_states);
+
+            let Some(bytecode) = self
+                .custom_shaders
+                .get(shader)
+                .map(|custom_shader| custom_shader.bytecode.clone())
+            else {
+                continue;
+            };
+            if let Err(error) = device_objects.pipeline_cache.get_or_create(
+                &self.device,
+                &device_objects.root_signature,
+                &RenderTargetDesc::new(self.rtv_format),
+                Some((shader, &bytecode)),
+            ) {
+                // Forget the failure, so the old bytecode is tried again
+                device_objects.pipeline_cache.remove_pixel_shader(shader);
+                self.custom_shaders
+                    .reject_reload(shader, previous, error.message());
+            }
+        }
+    }
+
+    /// Draws `texture_id` with a custom pixel shader, or with the built-in one
+    /// if `shader` is `None`.  Texture ids depend on whether bindless textures
+    /// are enabled, so this should be called after choosing.
+    pub fn set_texture_pixel_shader(&mut self, texture_id: TextureId, shader: Option<ShaderId>) {
+        self.custom_shaders.set_texture_shader(texture_id, shader);
+    }
+
+    fn descriptor_size(&self) -> u64 {
+        unsafe {
+            self.device
+                .GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV)
+                as u64
+        }
+    }
+}
+
+// The parts of the Renderer's configuration that the device objects are built
+// from.
+struct DeviceObjectsSettings<'a> {
+    rtv_format: DXGI_FORMAT,
+    font_srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    font_texture_id: TextureId,
+    num_frames_in_flight: usize,
+    pipeline_library: Option<&'a PipelineLibrarySource>,
+    bindless_textures: bool,
+    shader_compile_options: &'a ShaderCompileOptions,
+}
+
+impl DeviceObjects {
+    fn new(
+        context: &mut Context,
+        device: &ID3D12Device,
+        settings: &DeviceObjectsSettings,
+    ) -> Result<Self> {
+        let bindless_textures = settings.bindless_textures;
+        let root_signature = Self::create_root_signature(device, bindless_textures)?;
+
+        let vertex_shader =
+            Self::create_vertex_shader(settings.shader_compile_options, bindless_textures)?;
+        let pixel_shader =
+            Self::create_pixel_shader(settings.shader_compile_options, bindless_textures)?;
+        let mut pipeline_cache = PipelineCache::new(
+            vertex_shader,
+            pixel_shader,
+            if bindless_textures {
+                "bindless"
+            } else {
+                "descriptor table"
+            },
+        );
+        pipeline_cache.set_library(
+            settings
+                .pipeline_library
+                .and_then(|source| PipelineLibrary::new(device, source)),
+        );
+        pipeline_cache.get_or_create(
+            device,
+            &root_signature,
+            &RenderTargetDesc::new(settings.rtv_format),
+            None,
+        )?;
+
+        let font_texture = Self::create_fonts_texture(
+            device,
+            context,
+            settings.font_srv_cpu_desc_handle,
+            settings.font_texture_id,
+        )?;
+
+        let mut frame_resources: Vec<RenderBuffers> = Vec::new();
+        frame_resources.resize_with(settings.num_frames_in_flight, RenderBuffers::default);
+
+        Ok(DeviceObjects {
+            root_signature,
+            pipeline_cache,
+            font_texture,
+            frame_resources,
+            current_frame_resources: 0,
+            gpu_timer: None,
+            placeholder_texture: None,
+        })
+    }
+
+    fn rebuild_fonts(
+        &mut self,
+        context: &mut Context,
+        device: &ID3D12Device,
+        font_srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+        font_texture_id: TextureId,
+    ) -> Result<ID3D12Resource> {
+        let font_texture =
+            Self::create_fonts_texture(device, context, font_srv_cpu_desc_handle, font_texture_id)?;
+
+        Ok(std::mem::replace(&mut self.font_texture, font_texture))
+    }
+
+    fn create_placeholder_texture(
+        device: &ID3D12Device,
+        placeholder: PlaceholderDescriptor,
+    ) -> Result<ID3D12Resource> {
+        const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+
+        let texture = create_texture_resource(
+            device,
+            1,
+            1,
+            DXGI_FORMAT_R8G8B8A8_UNORM,
+            1,
+            &MAGENTA,
+            h!("imgui placeholder texture"),
+        )?;
+        create_rgba32_srv(device, &texture, placeholder.cpu_desc_handle);
+
+        Ok(texture)
+    }
+
+    // Picks the frame resources for the next frame, reusing them only once the
+    // GPU has finished with them.  They're tried in turn, so with steady frame
+    // pacing this cycles through them like the modulo scheme.  If they're all
+    // in use then more are allocated, up to max_frame_resources, and after
+    // that this waits for the oldest.  Frames that haven't been signaled yet
+    // can't be waited for, so if they're all that's left this fails.
+    fn acquire_frame_resources(
+        &mut self,
+        frame_fence: &FrameFence,
+        max_frame_resources: usize,
+    ) -> Result<usize> {
+        let completed_value = unsafe { frame_fence.fence.GetCompletedValue() };
+        let count = self.frame_resources.len();
+
+        let mut oldest: Option<usize> = None;
+        for offset in 1..=count {
+            let index = (self.current_frame_resources + offset) % count;
+            let fence_value = self.frame_resources[index].fence_value;
+            if fence_value <= completed_value {
+                return Ok(index);
+            }
+
+            // Frames recorded since the last signal can't be waited for
+            if fence_value < frame_fence.next_value
+                && oldest.map_or(true, |oldest| {
+                    fence_value < self.frame_resources[oldest].fence_value
+                })
+            {
+                oldest = Some(index);
+            }
+        }
+
+        if count < max_frame_resources {
+            self.frame_resources.push(RenderBuffers::default());
+            return Ok(count);
+        }
+
+        let Some(index) = oldest else {
+            return Err(DXGI_ERROR_WAS_STILL_DRAWING.into());
+        };
+        unsafe { wait_for_fence(&frame_fence.fence, self.frame_resources[index].fence_value) }?;
+        Ok(index)
+    }
+
+    fn create_root_signature(
+        device: &ID3D12Device,
+        bindless_textures: bool,
+    ) -> Result<ID3D12RootSignature> {
+        // Bilinear sampling is required by default. Set 'io.Fonts->Flags |=
+        // ImFontAtlasFlags_NoBakedLines' or 'style.AntiAliasedLinesUseTex =
+        // false' to allow point/nearest sampling.
+        let static_sampler = D3D12_STATIC_SAMPLER_DESC {
+            Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+            AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+            MipLODBias: 0.0,
+            MaxAnisotropy: 0,
+            ComparisonFunc: D3D12_COMPARISON_FUNC_ALWAYS,
+            BorderColor: D3D12_STATIC_BORDER_COLOR_TRANSPARENT_BLACK,
+            MinLOD: 0.0,
+            MaxLOD: 0.0,
+            ShaderRegister: 0,
+            RegisterSpace: 0,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        };
+
+        let mut flags = D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT
+            | D3D12_ROOT_SIGNATURE_FLAG_DENY_HULL_SHADER_ROOT_ACCESS
+            | D3D12_ROOT_SIGNATURE_FLAG_DENY_DOMAIN_SHADER_ROOT_ACCESS
+            | D3D12_ROOT_SIGNATURE_FLAG_DENY_GEOMETRY_SHADER_ROOT_ACCESS;
+        if bindless_textures {
+            flags |= D3D12_ROOT_SIGNATURE_FLAG_CBV_SRV_UAV_HEAP_DIRECTLY_INDEXED;
+        }
+
+        let projection_constants = D3D12_ROOT_CONSTANTS {
+            ShaderRegister: 0,
+            RegisterSpace: 0,
+            Num32BitValues: 16,
+        };
+
+        // Devices that support bindless textures always support version 1.1
+        let version = match bindless_textures {
+            true => D3D_ROOT_SIGNATURE_VERSION_1_1,
+            false => Capabilities::query(device).root_signature_version,
+        };
+
+        let mut signature = None;
+        let signature = match version {
+            D3D_ROOT_SIGNATURE_VERSION_1_0 => {
+                let desc_range = D3D12_DESCRIPTOR_RANGE {
+                    RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+                    NumDescriptors: 1,
+                    ..Default::default()
+                };
+
+                let param = [
+                    D3D12_ROOT_PARAMETER {
+                        ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+                        Anonymous: D3D12_ROOT_PARAMETER_0 {
+                            Constants: projection_constants,
+                        },
+                        ShaderVisibility: D3D12_SHADER_VISIBILITY_VERTEX,
+                    },
+                    D3D12_ROOT_PARAMETER {
+                        ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+                        Anonymous: D3D12_ROOT_PARAMETER_0 {
+                            DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                                NumDescriptorRanges: 1,
+                                pDescriptorRanges: &desc_range,
+                            },
+                        },
+                        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+                    },
+                ];
+
+                let desc = D3D12_ROOT_SIGNATURE_DESC {
+                    NumParameters: param.len() as u32,
+                    pParameters: param.as_ptr(),
+                    NumStaticSamplers: 1,
+                    pStaticSamplers: &static_sampler,
+                    Flags: flags,
+                };
+
+                unsafe {
+                    D3D12SerializeRootSignature(
+                        &desc,
+                        D3D_ROOT_SIGNATURE_VERSION_1_0,
+                        &mut signature,
+                        None,
+                    )
+                }
+            }
+            _ => {
+                // The descriptors are static: the renderer only rewrites the
+                // font descriptor once the frames that may read it have
+                // finished, and apps must do the same with theirs (see
+                // Capabilities).  The textures themselves don't change while
+                // the UI is being drawn.
+                let desc_range = D3D12_DESCRIPTOR_RANGE1 {
+                    RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+                    NumDescriptors: 1,
+                    Flags: D3D12_DESCRIPTOR_RANGE_FLAG_DATA_STATIC_WHILE_SET_AT_EXECUTE,
+                    ..Default::default()
+                };
+
+                let param = [
+                    D3D12_ROOT_PARAMETER1 {
+                        ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+                        Anonymous: D3D12_ROOT_PARAMETER1_0 {
+                            Constants: projection_constants,
+                        },
+                        ShaderVisibility: D3D12_SHADER_VISIBILITY_VERTEX,
+                    },
+                    if bindless_textures {
+                        // The texture's index in the descriptor heap
+                        D3D12_ROOT_PARAMETER1 {
+                            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+                            Anonymous: D3D12_ROOT_PARAMETER1_0 {
+                                Constants: D3D12_ROOT_CONSTANTS {
+                                    ShaderRegister: 1,
+                                    RegisterSpace: 0,
+                                    Num32BitValues: 1,
+                                },
+                            },
+                            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+                        }
+                    } else {
+                        D3D12_ROOT_PARAMETER1 {
+                            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+                            Anonymous: D3D12_ROOT_PARAMETER1_0 {
+                                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE1 {
+                                    NumDescriptorRanges: 1,
+                                    pDescriptorRanges: &desc_range,
+                                },
+                            },
+                            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+                        }
+                    },
+                ];
+
+                let desc = D3D12_VERSIONED_ROOT_SIGNATURE_DESC {
+                    Version: D3D_ROOT_SIGNATURE_VERSION_1_1,
+                    Anonymous: D3D12_VERSIONED_ROOT_SIGNATURE_DESC_0 {
+                        Desc_1_1: D3D12_ROOT_SIGNATURE_DESC1 {
+                            NumParameters: param.len() as u32,
+                            pParameters: param.as_ptr(),
+                            NumStaticSamplers: 1,
+                            pStaticSamplers: &static_sampler,
+                            Flags: flags,
+                        },
+                    },
+                };
+
+                unsafe { D3D12SerializeVersionedRootSignature(&desc, &mut signature, None) }
+            }
+        }
+        .map(|()| signature.unwrap())?;
+
+        unsafe {
+            device.CreateRootSignature(
+                0,
+                std::slice::from_raw_parts(
+                    signature.GetBufferPointer() as _,
+                    signature.GetBufferSize(),
+                ),
+            )
+        }
+    }
+
+    fn create_vertex_shader(
+        options: &ShaderCompileOptions,
+        bindless_textures: bool,
+    ) -> Result<ID3DBlob> {
+        const HLSL: &str = r"
+    cbuffer vertexBuffer: register(b0) {
+                float4x4 ProjectionMatrix;
+            };
+    
+            struct VS_INPUT {
+                float2 pos: POSITION;
+                float4 col: COLOR0;
+                float2 uv: TEXCOORD0;
+            };
+    
+            struct PS_INPUT {
+                float4 pos: SV_POSITION;
+                float4 col: COLOR0;
+                float2 uv: TEXCOORD0;
+            };
+    
+            PS_INPUT main(VS_INPUT input) {
+                PS_INPUT output;
                 output.pos = mul(ProjectionMatrix, float4(input.pos.xy, 0.f, 1.f));
                 output.col = input.col;
                 output.uv = input.uv;
                 return output;
             }
-    ",
-            s!("main"),
-            s!("vs_5_1"),
-        )?;
+    ";
+
+        options.compile_builtin(ShaderStage::Vertex, HLSL, bindless_textures)
+    }
 
+    // The vertex shader's input layout.  Semantic names are pointers, which
+    // aren't Send, so this is built whenever it's needed rather than stored.
+    fn input_layout() -> [D3D12_INPUT_ELEMENT_DESC; 3] {
         macro_rules! element {
             ($semantic:expr, $format:expr, $offset:expr) => {
                 D3D12_INPUT_ELEMENT_DESC {
@@ -398,7 +1630,7 @@ impl DeviceObjects {
             };
         }
 
-        let layout: [D3D12_INPUT_ELEMENT_DESC; 3] = [
+        [
             element!(
                 "POSITION",
                 DXGI_FORMAT_R32G32_FLOAT,
@@ -414,13 +1646,34 @@ impl DeviceObjects {
                 DXGI_FORMAT_R8G8B8A8_UNORM,
                 offset_of!(DrawVert::col).as_u32()
             ),
-        ];
-
-        Ok((shader, layout))
+        ]
     }
 
-    fn create_pixel_shader() -> Result<ID3DBlob> {
-        compile_shader(
+    fn create_pixel_shader(
+        options: &ShaderCompileOptions,
+        bindless_textures: bool,
+    ) -> Result<ID3DBlob> {
+        let hlsl = if bindless_textures {
+            r"
+    struct PS_INPUT {
+        float4 pos: SV_POSITION;
+        float4 col: COLOR0;
+        float2 uv: TEXCOORD0;
+    };
+    
+    cbuffer textureConstants: register(b1) {
+        uint textureIndex;
+    };
+    
+    SamplerState sampler0: register(s0);
+    
+    float4 main(PS_INPUT input): SV_Target {
+        Texture2D texture0 = ResourceDescriptorHeap[textureIndex];
+        float4 out_col = input.col * texture0.Sample(sampler0, input.uv);
+        return out_col;
+    }
+    "
+        } else {
             r"
     struct PS_INPUT {
         float4 pos: SV_POSITION;
@@ -435,132 +1688,220 @@ impl DeviceObjects {
         float4 out_col = input.col * texture0.Sample(sampler0, input.uv);
         return out_col;
     }
-    ",
-            s!("main"),
-            s!("ps_5_1"),
+    "
+        };
+
+        options.compile_builtin(ShaderStage::Pixel, hlsl, bindless_textures)
+    }
+
+    fn create_fonts_texture(
+        device: &ID3D12Device,
+        context: &mut Context,
+        font_srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+        font_texture_id: TextureId,
+    ) -> Result<ID3D12Resource> {
+        let font_atlas_texture = context.fonts().build_rgba32_texture();
+
+        let texture = create_texture_resource(
+            device,
+            font_atlas_texture.width,
+            font_atlas_texture.height,
+            DXGI_FORMAT_R8G8B8A8_UNORM,
+            1,
+            font_atlas_texture.data,
+            h!("imgui font texture"),
+        )?;
+
+        create_rgba32_srv(device, &texture, font_srv_cpu_desc_handle);
+
+        // Store the identifier
+        context.fonts().tex_id = font_texture_id;
+
+        Ok(texture)
+    }
+}
+
+// Returns the name given to a D3D12 object with SetName.
+fn debug_object_name(resource: &ID3D12Resource) -> Option<String> {
+    let mut size = 0u32;
+    unsafe { resource.GetPrivateData(&WKPDID_D3DDebugObjectNameW, &mut size, None) }.ok()?;
+
+    let mut name = vec![0u16; size as usize / 2];
+    unsafe {
+        resource.GetPrivateData(
+            &WKPDID_D3DDebugObjectNameW,
+            &mut size,
+            Some(name.as_mut_ptr() as *mut c_void),
         )
     }
+    .ok()?;
+
+    Some(
+        String::from_utf16_lossy(&name)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
 
-    fn create_fonts_texture(
-        device: &ID3D12Device,
-        context: &mut Context,
-        font_srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
-        font_srv_gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
-    ) -> Result<ID3D12Resource> {
-        let font_atlas_texture = context.fonts().build_rgba32_texture();
+// Creates a 2D texture from pixel data laid out as described by
+// `Renderer::create_texture`, waiting for the upload to complete.
+fn create_texture_resource(
+    device: &ID3D12Device,
+    width: u32,
+    height: u32,
+    format: DXGI_FORMAT,
+    mip_levels: u32,
+    data: &[u8],
+    name: &HSTRING,
+) -> Result<ID3D12Resource> {
+    if mip_levels == 0 || mip_levels > u16::MAX as u32 {
+        return Err(E_INVALIDARG.into());
+    }
 
-        // Upload texture to graphics system
-        unsafe {
-            // Create the destination texture resource
-            let resource_desc = D3D12_RESOURCE_DESC {
-                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
-                Width: font_atlas_texture.width as u64,
-                Height: font_atlas_texture.height,
-                DepthOrArraySize: 1,
-                MipLevels: 1,
-                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
-                SampleDesc: DXGI_SAMPLE_DESC {
-                    Count: 1,
-                    Quality: 0,
-                },
-                ..Default::default()
-            };
+    // Upload texture to graphics system
+    unsafe {
+        // Create the destination texture resource
+        let resource_desc = D3D12_RESOURCE_DESC {
+            Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+            Width: width as u64,
+            Height: height,
+            DepthOrArraySize: 1,
+            MipLevels: mip_levels as u16,
+            Format: format,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            ..Default::default()
+        };
 
-            let mut texture: Option<ID3D12Resource> = None;
+        // Where each mip level goes in the upload buffer.  The source rows
+        // are tightly packed, so they're row_sizes long.
+        let num_subresources = mip_levels as usize;
+        let mut layouts = vec![D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default(); num_subresources];
+        let mut num_rows = vec![0u32; num_subresources];
+        let mut row_sizes = vec![0u64; num_subresources];
+        let mut upload_size = 0u64;
+        device.GetCopyableFootprints(
+            &resource_desc,
+            0,
+            mip_levels,
+            0,
+            Some(layouts.as_mut_ptr()),
+            Some(num_rows.as_mut_ptr()),
+            Some(row_sizes.as_mut_ptr()),
+            Some(&mut upload_size),
+        );
 
-            device.CreateCommittedResource(
-                &D3D12_HEAP_PROPERTIES {
-                    Type: D3D12_HEAP_TYPE_DEFAULT,
-                    ..Default::default()
-                },
-                D3D12_HEAP_FLAG_NONE,
-                &resource_desc,
-                D3D12_RESOURCE_STATE_COPY_DEST,
-                None,
-                &mut texture,
-            )?;
-            let texture = texture.unwrap();
-            texture.SetName(w!("imgui font texture")).unwrap();
-
-            // Create the upload buffer resource
-            let upload_pitch =
-                (font_atlas_texture.width * 4).next_multiple_of(D3D12_TEXTURE_DATA_PITCH_ALIGNMENT);
-            let upload_size = font_atlas_texture.height * upload_pitch;
-
-            let resource_desc = D3D12_RESOURCE_DESC {
-                Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
-                Width: upload_size as u64,
-                Height: 1,
-                DepthOrArraySize: 1,
-                MipLevels: 1,
-                SampleDesc: DXGI_SAMPLE_DESC {
-                    Count: 1,
-                    Quality: 0,
-                },
-                Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        if upload_size == u64::MAX {
+            return Err(Error::new(E_INVALIDARG, "invalid texture description"));
+        }
+        let footprints: Vec<SubresourceFootprint> = layouts
+            .iter()
+            .zip(&num_rows)
+            .zip(&row_sizes)
+            .map(|((layout, num_rows), row_size)| SubresourceFootprint {
+                offset: layout.Offset,
+                row_pitch: layout.Footprint.RowPitch as u64,
+                num_rows: *num_rows,
+                row_size: *row_size,
+            })
+            .collect();
+        let copies = plan_texture_upload(&footprints, upload_size, data.len())
+            .map_err(|message| Error::new(E_INVALIDARG, message))?;
+
+        let mut texture: Option<ID3D12Resource> = None;
+
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
                 ..Default::default()
-            };
-
-            let mut upload_buffer: Option<ID3D12Resource> = None;
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &resource_desc,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            None,
+            &mut texture,
+        )?;
+        let texture = texture.unwrap();
+        texture.SetName(name)?;
+
+        // Create the upload buffer resource
+        let resource_desc = D3D12_RESOURCE_DESC {
+            Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+            Width: upload_size,
+            Height: 1,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            ..Default::default()
+        };
 
-            device.CreateCommittedResource(
-                &D3D12_HEAP_PROPERTIES {
-                    Type: D3D12_HEAP_TYPE_UPLOAD,
-                    ..Default::default()
-                },
-                D3D12_HEAP_FLAG_NONE,
-                &resource_desc,
-                D3D12_RESOURCE_STATE_GENERIC_READ,
-                None,
-                &mut upload_buffer,
-            )?;
+        let mut upload_buffer: Option<ID3D12Resource> = None;
 
-            let upload_buffer = upload_buffer.unwrap();
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_UPLOAD,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &resource_desc,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+            None,
+            &mut upload_buffer,
+        )?;
 
-            // Copy the texture data into the upload buffer
-            let mut mapped = std::ptr::null_mut();
-            upload_buffer.Map(
-                0,
-                Some(&D3D12_RANGE {
-                    Begin: 0,
-                    End: upload_size as usize,
-                }),
-                Some(&mut mapped),
-            )?;
-            let mapped: *mut u8 = mapped.cast();
+        let upload_buffer = upload_buffer.unwrap();
 
-            for y in 0..font_atlas_texture.height {
-                let size = font_atlas_texture.width as usize * 4;
+        // Copy the texture data into the upload buffer
+        let mut mapped = std::ptr::null_mut();
+        upload_buffer.Map(
+            0,
+            Some(&D3D12_RANGE {
+                Begin: 0,
+                End: upload_size as usize,
+            }),
+            Some(&mut mapped),
+        )?;
+        let mapped = std::slice::from_raw_parts_mut(mapped.cast::<u8>(), upload_size as usize);
+
+        for copy in &copies {
+            copy_rows(
+                &data[copy.src_range.clone()],
+                &mut mapped[copy.dst_range.clone()],
+                copy.row_size,
+                copy.row_pitch,
+            );
+        }
+        upload_buffer.Unmap(0, None);
 
-                let dest = std::slice::from_raw_parts_mut(
-                    mapped.offset((y * upload_pitch) as isize),
-                    size,
-                );
+        let fence: ID3D12Fence = device.CreateFence(0, D3D12_FENCE_FLAG_NONE)?;
 
-                let src_start = (y * font_atlas_texture.width * 4) as usize;
-                let src_end = src_start + size;
+        let cmd_queue: ID3D12CommandQueue =
+            device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
+                Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
+                NodeMask: 1,
+                ..Default::default()
+            })?;
 
-                let src = &font_atlas_texture.data[src_start..src_end];
+        let cmd_allocator: ID3D12CommandAllocator =
+            device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)?;
 
-                dest.copy_from_slice(src);
-            }
-            upload_buffer.Unmap(0, None);
+        let cmd_list: ID3D12GraphicsCommandList =
+            device.CreateCommandList(0, D3D12_COMMAND_LIST_TYPE_DIRECT, &cmd_allocator, None)?;
 
-            // Copy the upload buffer into the destination texture
+        // Copy the upload buffer into the destination texture, a mip level at
+        // a time
+        for (subresource, layout) in layouts.iter().enumerate() {
             let src_location = D3D12_TEXTURE_COPY_LOCATION {
                 pResource: std::mem::ManuallyDrop::new(std::mem::transmute_copy(&upload_buffer)),
                 Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
                 Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
-                    PlacedFootprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
-                        Footprint: D3D12_SUBRESOURCE_FOOTPRINT {
-                            Format: DXGI_FORMAT_R8G8B8A8_UNORM,
-                            Width: font_atlas_texture.width,
-                            Height: font_atlas_texture.height,
-                            Depth: 1,
-                            RowPitch: upload_pitch,
-                        },
-                        ..Default::default()
-                    },
+                    PlacedFootprint: *layout,
                 },
             };
 
@@ -568,223 +1909,716 @@ impl DeviceObjects {
                 pResource: std::mem::transmute_copy(&texture),
                 Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
                 Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
-                    SubresourceIndex: 0,
+                    SubresourceIndex: subresource as u32,
                 },
             };
 
-            let barrier = D3D12_RESOURCE_BARRIER {
-                Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                Anonymous: D3D12_RESOURCE_BARRIER_0 {
-                    Transition: std::mem::ManuallyDrop::new(D3D12_RESOURCE_TRANSITION_BARRIER {
-                        pResource: std::mem::transmute_copy(&texture),
-                        Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                        StateBefore: D3D12_RESOURCE_STATE_COPY_DEST,
-                        StateAfter: D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
-                    }),
-                },
-                ..Default::default()
+            cmd_list.CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, None);
+        }
+
+        let barrier = D3D12_RESOURCE_BARRIER {
+            Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+            Anonymous: D3D12_RESOURCE_BARRIER_0 {
+                Transition: std::mem::ManuallyDrop::new(D3D12_RESOURCE_TRANSITION_BARRIER {
+                    pResource: std::mem::transmute_copy(&texture),
+                    Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+                    StateBefore: D3D12_RESOURCE_STATE_COPY_DEST,
+                    StateAfter: D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                }),
+            },
+            ..Default::default()
+        };
+
+        cmd_list.ResourceBarrier(&[barrier]);
+        cmd_list.Close()?;
+
+        cmd_queue.ExecuteCommandLists(&[Some(cmd_list.cast().unwrap())]);
+        cmd_queue.Signal(&fence, 1)?;
+        wait_for_fence(&fence, 1)?;
+
+        Ok(texture)
+    }
+}
+
+// Where a subresource's rows go in an upload buffer, as reported by
+// GetCopyableFootprints.  For block compressed formats a row is a row of 4x4
+// blocks.
+#[derive(Clone, Copy, Debug)]
+struct SubresourceFootprint {
+    offset: u64,
+    row_pitch: u64,
+    num_rows: u32,
+    row_size: u64,
+}
+
+// A copy of one subresource's tightly packed rows from the app's data into the
+// upload buffer.
+#[derive(Debug, PartialEq)]
+struct SubresourceCopy {
+    src_range: std::ops::Range<usize>,
+    dst_range: std::ops::Range<usize>,
+    row_size: usize,
+    row_pitch: usize,
+}
+
+// Works out the copies that put `data_len` bytes of tightly packed
+// subresources, one after the other, into an upload buffer of `upload_size`
+// bytes laid out as `footprints` describes.  Fails, with a message, if the
+// data is the wrong size or a subresource doesn't fit the buffer.
+fn plan_texture_upload(
+    footprints: &[SubresourceFootprint],
+    upload_size: u64,
+    data_len: usize,
+) -> std::result::Result<Vec<SubresourceCopy>, String> {
+    let data_size: u64 = footprints
+        .iter()
+        .map(|footprint| footprint.row_size * footprint.num_rows as u64)
+        .sum();
+    if data_len as u64 != data_size {
+        return Err(format!(
+            "expected {data_size} bytes of texture data, got {data_len}"
+        ));
+    }
+
+    let mut src_offset = 0;
+    footprints
+        .iter()
+        .map(|footprint| {
+            let src_size = footprint.row_size * footprint.num_rows as u64;
+            let dst_size = match footprint.num_rows {
+                0 => 0,
+                num_rows => footprint.row_pitch * (num_rows as u64 - 1) + footprint.row_size,
             };
+            if footprint.row_size > footprint.row_pitch || footprint.offset + dst_size > upload_size
+            {
+                return Err("texture footprint doesn't fit the upload buffer".to_string());
+            }
+
+            let copy = SubresourceCopy {
+                src_range: src_offset as usize..(src_offset + src_size) as usize,
+                dst_range: footprint.offset as usize..(footprint.offset + dst_size) as usize,
+                row_size: footprint.row_size as usize,
+                row_pitch: footprint.row_pitch as usize,
+            };
+            src_offset += src_size;
+            Ok(copy)
+        })
+        .collect()
+}
 
-            let fence: ID3D12Fence = device.CreateFence(0, D3D12_FENCE_FLAG_NONE)?;
-            let event = CreateEventA(None, false, false, None)?;
+// Copies tightly packed rows of `row_size` bytes from `src` into `dst`, where
+// each row starts `row_pitch` bytes after the previous one.  The copy stops
+// at the end of `src`, and the padding between rows is left untouched.
+fn copy_rows(src: &[u8], dst: &mut [u8], row_size: usize, row_pitch: usize) {
+    if row_size == 0 {
+        return;
+    }
 
-            let cmd_queue: ID3D12CommandQueue =
-                device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
-                    Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
-                    NodeMask: 1,
-                    ..Default::default()
-                })?;
+    for (src_row, dst_row) in src.chunks_exact(row_size).zip(dst.chunks_mut(row_pitch)) {
+        dst_row[..row_size].copy_from_slice(src_row);
+    }
+}
 
-            let cmd_allocator: ID3D12CommandAllocator =
-                device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)?;
+// Blocks until `fence` reaches `value`.
+pub(crate) unsafe fn wait_for_fence(fence: &ID3D12Fence, value: u64) -> Result<()> {
+    let event = CreateEventA(None, false, false, None)?;
 
-            let cmd_list: ID3D12GraphicsCommandList = device.CreateCommandList(
-                0,
-                D3D12_COMMAND_LIST_TYPE_DIRECT,
-                &cmd_allocator,
-                None,
-            )?;
+    fence.SetEventOnCompletion(value, event)?;
+    WaitForSingleObject(event, INFINITE);
 
-            cmd_list.CopyTextureRegion(&dst_location, 0, 0, 0, &src_location, None);
-            cmd_list.ResourceBarrier(&[barrier]);
-            cmd_list.Close()?;
-
-            cmd_queue.ExecuteCommandLists(&[Some(cmd_list.cast().unwrap())]);
-            cmd_queue.Signal(&fence, 1)?;
-
-            fence.SetEventOnCompletion(1, event)?;
-            WaitForSingleObject(event, INFINITE);
-
-            CloseHandle(event)?;
-
-            // Create the texture view
-            device.CreateShaderResourceView(
-                &texture,
-                Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
-                    Format: DXGI_FORMAT_R8G8B8A8_UNORM,
-                    ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
-                    Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
-                    Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
-                        Texture2D: D3D12_TEX2D_SRV {
-                            MipLevels: 1,
-                            ..Default::default()
-                        },
+    CloseHandle(event)
+}
+
+fn create_rgba32_srv(
+    device: &ID3D12Device,
+    texture: &ID3D12Resource,
+    srv_cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+) {
+    unsafe {
+        // Create the texture view
+        device.CreateShaderResourceView(
+            texture,
+            Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+                Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+                Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                    Texture2D: D3D12_TEX2D_SRV {
+                        MipLevels: 1,
+                        ..Default::default()
                     },
-                }),
-                font_srv_cpu_desc_handle,
-            );
+                },
+            }),
+            srv_cpu_desc_handle,
+        );
+    }
+}
 
-            // Store the identifier
-            context.fonts().tex_id = TextureId::new(font_srv_gpu_desc_handle.ptr as usize);
+/// Optional device features the renderer uses when they're available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The version the root signature is serialized with.  Version 1.1 lets
+    /// the driver assume that the SRV descriptors the UI's textures are read
+    /// through, and the textures themselves, don't change from when
+    /// `render_draw_data` records a frame until the GPU has finished it, so
+    /// the app must not rewrite them in the meantime.
+    pub root_signature_version: D3D_ROOT_SIGNATURE_VERSION,
+
+    /// Whether `Renderer::enable_bindless_textures` can be used: the device
+    /// supports shader model 6.6 and resource binding tier 3.
+    pub bindless_textures: bool,
+}
+
+impl Capabilities {
+    fn query(device: &ID3D12Device) -> Self {
+        let mut root_signature = D3D12_FEATURE_DATA_ROOT_SIGNATURE {
+            HighestVersion: D3D_ROOT_SIGNATURE_VERSION_1_1,
+        };
+        let root_signature_version = match check_feature_support(
+            device,
+            D3D12_FEATURE_ROOT_SIGNATURE,
+            &mut root_signature,
+        ) {
+            true if root_signature.HighestVersion.0 >= D3D_ROOT_SIGNATURE_VERSION_1_1.0 => {
+                D3D_ROOT_SIGNATURE_VERSION_1_1
+            }
+            _ => D3D_ROOT_SIGNATURE_VERSION_1_0,
+        };
 
-            Ok(texture)
+        let mut shader_model = D3D12_FEATURE_DATA_SHADER_MODEL {
+            HighestShaderModel: D3D_SHADER_MODEL_6_6,
+        };
+        let mut options = D3D12_FEATURE_DATA_D3D12_OPTIONS::default();
+        let bindless_textures =
+            check_feature_support(device, D3D12_FEATURE_SHADER_MODEL, &mut shader_model)
+                && shader_model.HighestShaderModel.0 >= D3D_SHADER_MODEL_6_6.0
+                && check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS, &mut options)
+                && options.ResourceBindingTier.0 >= D3D12_RESOURCE_BINDING_TIER_3.0;
+
+        Capabilities {
+            root_signature_version,
+            bindless_textures,
         }
     }
 }
 
-fn compile_shader(hlsl: &str, entry_point: PCSTR, target: PCSTR) -> Result<ID3DBlob> {
-    let mut shader = None;
-    let mut errors: Option<ID3DBlob> = None;
-    let result = unsafe {
-        D3DCompile(
-            hlsl.as_ptr() as *const c_void,
-            hlsl.len(),
-            None,
-            None,
-            None,
-            entry_point,
-            target,
-            0,
-            0,
-            &mut shader,
-            Some(&mut errors),
-        )
+fn check_feature_support<T>(device: &ID3D12Device, feature: D3D12_FEATURE, data: &mut T) -> bool {
+    unsafe {
+        device
+            .CheckFeatureSupport(
+                feature,
+                data as *mut T as *mut c_void,
+                std::mem::size_of::<T>() as u32,
+            )
+            .is_ok()
     }
-    .map(|()| shader.unwrap());
+}
 
-    if result.is_err() {
-        unsafe {
-            let error_blob = errors.unwrap();
-            let _ = io::stdout().write_all(std::slice::from_raw_parts(
-                error_blob.GetBufferPointer() as *const u8,
-                error_blob.GetBufferSize(),
-            ));
+// render_draw_data
+
+/// Options controlling how [`Renderer::render_draw_data_with_options`] draws
+/// the UI.
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// The rectangle of the bound render target, in pixels, to draw the UI
+    /// into.  The UI is scaled to fill it and all clip rectangles are clamped
+    /// to it.  If this is `None` the UI is drawn at the top left of the render
+    /// target, sized by the draw data's display size and framebuffer scale.
+    pub target_rect: Option<RECT>,
+
+    /// Merge adjacent draw commands that use the same texture and clip
+    /// rectangle into a single draw call.
+    pub merge_draw_calls: bool,
+
+    /// The configuration of the bound render target.  If this is `None` it's
+    /// assumed to be a single sampled target of the format passed to
+    /// [`Renderer::new`], with alpha blending.
+    pub render_target: Option<RenderTargetDesc>,
+
+    /// Bind this render target and record the UI inside a render pass on it,
+    /// rather than drawing into the render target the caller has bound.  If
+    /// the command list doesn't support render passes the target is bound,
+    /// and cleared, with `OMSetRenderTargets` and `ClearRenderTargetView`.
+    pub render_pass: Option<RenderPass>,
+}
+
+/// Statistics describing the work done by the renderer for a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// The number of draw calls issued.
+    pub draw_calls: usize,
+
+    /// The number of draw commands skipped because their clip rectangle was
+    /// empty.
+    pub draw_calls_clipped: usize,
+
+    /// The number of draw commands merged into the preceding draw call.
+    pub merged_draw_calls: usize,
+
+    /// The number of texture binds skipped because the texture was already
+    /// bound.
+    pub texture_binds_skipped: usize,
+
+    /// The number of scissor rect changes skipped because the scissor rect was
+    /// already set.
+    pub scissor_rects_skipped: usize,
+
+    /// The number of vertices uploaded.
+    pub vertices_uploaded: usize,
+
+    /// The number of indices uploaded.
+    pub indices_uploaded: usize,
+
+    /// The number of bytes written to the upload heap.
+    pub upload_bytes: usize,
+
+    /// The number of times the vertex buffer had to be reallocated, including
+    /// when it was shrunk.
+    pub vertex_buffer_reallocations: usize,
+
+    /// The number of times the index buffer had to be reallocated, including
+    /// when it was shrunk.
+    pub index_buffer_reallocations: usize,
+
+    /// The number of times the vertex buffer was shrunk.
+    pub vertex_buffer_shrinks: usize,
+
+    /// The number of times the index buffer was shrunk.
+    pub index_buffer_shrinks: usize,
+
+    /// The number of vertices the vertex buffer can hold.
+    pub vertex_buffer_capacity: usize,
+
+    /// The number of indices the index buffer can hold.
+    pub index_buffer_capacity: usize,
+
+    /// The number of user callbacks invoked.
+    pub callbacks: usize,
+
+    /// The number of draw commands that used an invalid texture id.  Only
+    /// counted when texture validation is enabled.
+    pub invalid_texture_ids: usize,
+
+    /// The number of textures drawn with the built-in pixel shader because a
+    /// pipeline state couldn't be created with their custom pixel shader.
+    pub pixel_shader_fallbacks: usize,
+}
+
+/// How the vertex and index buffers, one pair per frame in flight, grow when
+/// they are too small.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BufferGrowthPolicy {
+    /// Grow to the required size plus a fixed number of spare elements.
+    FixedSlack { vertices: usize, indices: usize },
+
+    /// Grow to at least `factor` times the current size.
+    Geometric { factor: f32 },
+
+    /// Allocate buffers of this size up front, and only grow them, to exactly
+    /// the required size, if they turn out to be too small.
+    Preallocated { vertices: usize, indices: usize },
+}
+
+impl Default for BufferGrowthPolicy {
+    fn default() -> Self {
+        BufferGrowthPolicy::FixedSlack {
+            vertices: 5000,
+            indices: 10000,
         }
     }
+}
 
-    result
+/// How the vertex and index buffers are sized, set with
+/// [`Renderer::set_buffer_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BufferPolicy {
+    /// How a buffer grows when it's too small for a frame.
+    pub growth: BufferGrowthPolicy,
+
+    /// If set, a buffer is shrunk once it has been larger than the growth
+    /// policy calls for in this many consecutive frames that used it.  It is
+    /// shrunk to fit the largest of those frames.
+    pub shrink_after_idle_frames: Option<usize>,
 }
 
-// render_draw_data
+#[derive(Clone, Copy)]
+enum BufferKind {
+    Vertex,
+    Index,
+}
+
+impl BufferGrowthPolicy {
+    fn capacity_for(&self, kind: BufferKind, required: usize, current: usize) -> usize {
+        let pick = |vertices: usize, indices: usize| match kind {
+            BufferKind::Vertex => vertices,
+            BufferKind::Index => indices,
+        };
+
+        let capacity = match *self {
+            BufferGrowthPolicy::FixedSlack { vertices, indices } => {
+                required + pick(vertices, indices)
+            }
+            BufferGrowthPolicy::Geometric { factor } => {
+                required.max((current as f32 * factor.max(1.0)).ceil() as usize)
+            }
+            BufferGrowthPolicy::Preallocated { vertices, indices } => {
+                required.max(pick(vertices, indices))
+            }
+        };
+
+        // Zero sized buffers can't be created
+        capacity.max(1)
+    }
+}
 
 impl Renderer {
     pub fn render_draw_data(
         &mut self,
         draw_data: &DrawData,
         graphics_command_list: &ID3D12GraphicsCommandList,
-    ) {
+    ) -> Result<()> {
+        self.render_draw_data_with_options(
+            draw_data,
+            graphics_command_list,
+            &RenderOptions::default(),
+        )
+    }
+
+    /// Records the draw data into `graphics_command_list`.  If this fails,
+    /// nothing is recorded and the UI is missing from the frame, but the
+    /// renderer can carry on with the next one.
+    pub fn render_draw_data_with_options(
+        &mut self,
+        draw_data: &DrawData,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+        options: &RenderOptions,
+    ) -> Result<()> {
         if draw_data.display_size.iter().any(|size| *size <= 0.0) {
-            return;
+            return Ok(());
         }
 
-        if let Some(device_objects) = self.device_objects.as_mut() {
-            self.frame_index = self.frame_index.wrapping_add(1);
-            device_objects.render_draw_data(
-                &self.device,
-                self.frame_index % self.num_frames_in_flight,
-                draw_data,
-                graphics_command_list,
-            );
+        let Some(target) = TargetTransform::new(draw_data, options.target_rect) else {
+            return Ok(());
+        };
+
+        self.texture_validation.invalid_texture_ids.clear();
+        let font_texture_id = self.texture_id(self.font_srv_gpu_desc_handle);
+
+        let Some(device_objects) = self.device_objects.as_mut() else {
+            return Ok(());
+        };
+
+        self.frame_index = self.frame_index.wrapping_add(1);
+        self.deletion_queue.begin_frame(
+            &self.frame_fence,
+            self.frame_index,
+            self.num_frames_in_flight,
+        );
+
+        let frame_fence = &self.frame_fence;
+        let frame_index = self.frame_index;
+        let num_frames_in_flight = self.num_frames_in_flight;
+        self.texture_views.release_retired(|view| {
+            frame_fence.has_completed(
+                frame_index,
+                num_frames_in_flight,
+                view.last_used_frame,
+                view.fence_value,
+            )
+        });
+
+        let frame_resources_index = if self.frame_fence.signaled {
+            device_objects.acquire_frame_resources(
+                &self.frame_fence,
+                self.num_frames_in_flight * MAX_FRAME_RESOURCES_FACTOR,
+            )?
+        } else {
+            self.frame_index % self.num_frames_in_flight
+        };
+        device_objects.current_frame_resources = frame_resources_index;
+
+        let render_target = options
+            .render_target
+            .unwrap_or_else(|| RenderTargetDesc::new(self.rtv_format));
+        let pipelines =
+            device_objects.texture_pipelines(&self.device, &render_target, &self.custom_shaders)?;
+        let upload_stats = device_objects.frame_resources[frame_resources_index].upload(
+            &self.device,
+            draw_data,
+            &self.buffer_policy,
+            &mut self.deletion_queue,
+        )?;
+
+        // Everything the UI draws, and the inspector reads, is a shader
+        // resource from here on
+        self.viewport_images.begin_ui(graphics_command_list);
+
+        self.texture_inspector.record_readback(
+            &self.device,
+            graphics_command_list,
+            &self.frame_fence,
+            self.frame_index,
+            self.num_frames_in_flight,
+        );
+
+        let mut settings = FrameSettings {
+            target,
+            render_target,
+            merge_draw_calls: options.merge_draw_calls,
+            render_pass: options.render_pass,
+            bindless_textures: self.bindless_textures.is_some(),
+            frame_index: self.frame_index,
+            draw_callbacks: &mut self.draw_callbacks,
+            texture_validation: if self.texture_validation.is_enabled() {
+                // The font texture is always valid
+                self.texture_validation.register_texture_id(font_texture_id);
+                Some(&mut self.texture_validation)
+            } else {
+                None
+            },
+        };
+
+        self.last_frame_stats = device_objects.render_draw_data(
+            frame_resources_index,
+            &pipelines,
+            draw_data,
+            &mut settings,
+            upload_stats,
+            graphics_command_list,
+        );
+
+        self.viewport_images.end_ui(graphics_command_list);
+        device_objects.frame_resources[frame_resources_index].fence_value =
+            self.frame_fence.next_value;
+        self.frame_fence.unsignaled_frames = true;
+        self.last_frame_stats.invalid_texture_ids =
+            self.texture_validation.invalid_texture_ids.len();
+        self.last_frame_stats.pixel_shader_fallbacks = pipelines.fallbacks;
+        Ok(())
+    }
+
+    /// Returns statistics for the most recently rendered frame.
+    pub fn last_frame_stats(&self) -> FrameStats {
+        self.last_frame_stats
+    }
+
+    /// Sets how the vertex and index buffers grow and shrink.  Takes effect
+    /// the next time each buffer is used.
+    pub fn set_buffer_policy(&mut self, buffer_policy: BufferPolicy) {
+        self.buffer_policy = buffer_policy;
+    }
+
+    /// Returns the policy set with `set_buffer_policy`.
+    pub fn buffer_policy(&self) -> BufferPolicy {
+        self.buffer_policy
+    }
+}
+
+// Settings that apply to a single call to render_draw_data, gathered from the
+// Renderer and the RenderOptions.
+struct FrameSettings<'a> {
+    target: TargetTransform,
+    render_target: RenderTargetDesc,
+    merge_draw_calls: bool,
+    render_pass: Option<RenderPass>,
+    bindless_textures: bool,
+    frame_index: usize,
+    draw_callbacks: &'a mut DrawCallbacks,
+    texture_validation: Option<&'a mut TextureValidation>,
+}
+
+// The pipeline states used in a frame: the one with the built-in pixel shader,
+// and those for textures that are drawn with custom pixel shaders.
+struct TexturePipelines {
+    default: ID3D12PipelineState,
+    textures: Vec<(TextureId, ID3D12PipelineState)>,
+    // Textures whose custom pixel shader couldn't be used, and that are drawn
+    // with the default pipeline state
+    fallbacks: usize,
+}
+
+impl TexturePipelines {
+    // 0 is the default pipeline state.
+    fn index(&self, texture: TextureId) -> usize {
+        self.textures
+            .iter()
+            .position(|(id, _)| *id == texture)
+            .map_or(0, |index| index + 1)
+    }
+
+    fn get(&self, index: usize) -> &ID3D12PipelineState {
+        match index {
+            0 => &self.default,
+            index => &self.textures[index - 1].1,
         }
     }
 }
 
 impl DeviceObjects {
-    fn render_draw_data(
+    // Gets the pipeline states for a frame before anything is recorded, so that
+    // if one can't be created, for example because the render target
+    // description is invalid, the frame can be skipped.  Textures whose custom
+    // pixel shader can't be used with the render target are drawn with the
+    // default pipeline state instead.
+    fn texture_pipelines(
         &mut self,
         device: &ID3D12Device,
+        render_target: &RenderTargetDesc,
+        custom_shaders: &CustomShaders,
+    ) -> Result<TexturePipelines> {
+        let default =
+            self.pipeline_cache
+                .get_or_create(device, &self.root_signature, render_target, None)?;
+
+        let mut textures = Vec::new();
+        let mut fallbacks = 0;
+        for &(texture_id, shader_id) in custom_shaders.texture_shaders() {
+            let Some(shader) = custom_shaders.get(shader_id) else {
+                continue;
+            };
+            match self.pipeline_cache.get_or_create(
+                device,
+                &self.root_signature,
+                render_target,
+                Some((shader_id, &shader.bytecode)),
+            ) {
+                Ok(pipeline_state) => textures.push((texture_id, pipeline_state)),
+                Err(_) => fallbacks += 1,
+            }
+        }
+
+        Ok(TexturePipelines {
+            default,
+            textures,
+            fallbacks,
+        })
+    }
+
+    fn render_draw_data(
+        &mut self,
         frame_index: usize,
+        pipelines: &TexturePipelines,
         draw_data: &DrawData,
+        settings: &mut FrameSettings,
+        upload_stats: FrameStats,
         graphics_command_list: &ID3D12GraphicsCommandList,
-    ) {
+    ) -> FrameStats {
         unsafe {
-            self.frame_resources[frame_index].render_draw_data(
-                device,
+            #[cfg(feature = "pix")]
+            pix::begin_event(graphics_command_list, "imgui");
+
+            if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+                gpu_timer.begin(frame_index, graphics_command_list);
+            }
+
+            // Barriers, copies and queries aren't allowed inside a render
+            // pass, so it only covers the UI's draw calls
+            let render_pass = settings.render_pass.map(|render_pass| {
+                begin_render_pass(
+                    graphics_command_list,
+                    &render_pass,
+                    settings.render_target.rtv_format,
+                )
+            });
+
+            let stats = self.frame_resources[frame_index].render_draw_data(
                 &self.root_signature,
-                &self.pipeline_state,
+                pipelines,
                 draw_data,
+                settings,
+                upload_stats,
                 graphics_command_list,
-            )
+            );
+
+            if let Some(render_pass) = render_pass {
+                render_pass.end();
+            }
+
+            if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+                gpu_timer.end(frame_index, graphics_command_list);
+            }
+
+            #[cfg(feature = "pix")]
+            pix::end_event(graphics_command_list);
+
+            stats
         }
     }
 }
 
 impl RenderBuffers {
-    unsafe fn render_draw_data(
+    // Fills the vertex and index buffers with the draw data, growing or
+    // shrinking them first if needed.  Records nothing, so that a failure
+    // leaves the command list untouched.
+    fn upload(
         &mut self,
         device: &ID3D12Device,
-        root_signature: &ID3D12RootSignature,
-        pipeline_state: &ID3D12PipelineState,
         draw_data: &DrawData,
-        graphics_command_list: &ID3D12GraphicsCommandList,
-    ) {
-        // Create and grow vertex/index buffers if needed
-        if self.vertex_buffer.is_none()
-            || self.vertex_buffer_size < draw_data.total_vtx_count as usize
-        {
-            self.vertex_buffer_size = draw_data.total_vtx_count as usize + 5000;
-            self.vertex_buffer = None;
-            self.vertex_buffer = Some(
-                Self::create_buffer(
-                    device,
-                    self.vertex_buffer_size * std::mem::size_of::<DrawVert>(),
-                )
-                .unwrap(),
-            );
+        policy: &BufferPolicy,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<FrameStats> {
+        let mut stats = FrameStats::default();
 
-            self.vertex_buffer
-                .as_mut()
-                .unwrap()
-                .SetName(&HSTRING::from(format!("imgui VB {}", self.vbcount)))
-                .unwrap();
-            self.vbcount += 1;
+        // Create, grow or shrink vertex/index buffers if needed
+        match self.vertex_buffer.reserve(
+            device,
+            BufferKind::Vertex,
+            draw_data.total_vtx_count as usize,
+            policy,
+            deletion_queue,
+        )? {
+            BufferChange::None => (),
+            BufferChange::Grown => stats.vertex_buffer_reallocations += 1,
+            BufferChange::Shrunk => {
+                stats.vertex_buffer_reallocations += 1;
+                stats.vertex_buffer_shrinks += 1;
+            }
         }
 
-        if self.index_buffer.is_none()
-            || self.index_buffer_size < draw_data.total_idx_count as usize
-        {
-            self.index_buffer_size = draw_data.total_idx_count as usize + 10000;
-            self.index_buffer = None;
-            self.index_buffer = Some(
-                Self::create_buffer(
-                    device,
-                    self.index_buffer_size * std::mem::size_of::<DrawIdx>(),
-                )
-                .unwrap(),
-            );
-
-            self.vertex_buffer
-                .as_mut()
-                .unwrap()
-                .SetName(&HSTRING::from(format!("imgui IB {}", self.ibcount)))
-                .unwrap();
-            self.ibcount += 1;
+        match self.index_buffer.reserve(
+            device,
+            BufferKind::Index,
+            draw_data.total_idx_count as usize,
+            policy,
+            deletion_queue,
+        )? {
+            BufferChange::None => (),
+            BufferChange::Grown => stats.index_buffer_reallocations += 1,
+            BufferChange::Shrunk => {
+                stats.index_buffer_reallocations += 1;
+                stats.index_buffer_shrinks += 1;
+            }
         }
 
-        // Upload vertex/index data into a single contiguous GPU buffer
-        let vertex_buffer = self.vertex_buffer.as_ref().unwrap();
-        let index_buffer = self.index_buffer.as_ref().unwrap();
-
-        let vtx_resource = Self::map(vertex_buffer);
-        let idx_resource = Self::map(index_buffer);
+        stats.vertex_buffer_capacity = self.vertex_buffer.capacity;
+        stats.index_buffer_capacity = self.index_buffer.capacity;
 
-        let vtx_dest = std::slice::from_raw_parts_mut(
-            vtx_resource.cast::<DrawVert>(),
-            self.vertex_buffer_size,
-        );
+        // Upload vertex/index data into a single contiguous GPU buffer
+        let vertex_buffer = self.vertex_buffer.resource.as_ref().unwrap();
+        let index_buffer = self.index_buffer.resource.as_ref().unwrap();
+
+        let vtx_resource = Self::map(vertex_buffer)?;
+        let idx_resource = match Self::map(index_buffer) {
+            Ok(idx_resource) => idx_resource,
+            Err(error) => {
+                unsafe { vertex_buffer.Unmap(0, None) };
+                return Err(error);
+            }
+        };
 
-        let idx_dest =
-            std::slice::from_raw_parts_mut(idx_resource.cast::<DrawIdx>(), self.index_buffer_size);
+        let (vtx_dest, idx_dest) = unsafe {
+            (
+                std::slice::from_raw_parts_mut(
+                    vtx_resource.cast::<DrawVert>(),
+                    self.vertex_buffer.capacity,
+                ),
+                std::slice::from_raw_parts_mut(
+                    idx_resource.cast::<DrawIdx>(),
+                    self.index_buffer.capacity,
+                ),
+            )
+        };
 
         let mut vtx_dest_index = 0;
         let mut idx_dest_index = 0;
@@ -801,13 +2635,35 @@ impl RenderBuffers {
             idx_dest_index += num_indices;
         }
 
-        vertex_buffer.Unmap(0, None);
-        index_buffer.Unmap(0, None);
+        unsafe {
+            vertex_buffer.Unmap(0, None);
+            index_buffer.Unmap(0, None);
+        }
+
+        stats.vertices_uploaded = vtx_dest_index;
+        stats.indices_uploaded = idx_dest_index;
+        stats.upload_bytes = vtx_dest_index * std::mem::size_of::<DrawVert>()
+            + idx_dest_index * std::mem::size_of::<DrawIdx>();
+
+        Ok(stats)
+    }
 
+    // Records the draw calls, using the buffers filled by upload.  `stats`
+    // holds the upload's statistics.
+    unsafe fn render_draw_data(
+        &mut self,
+        root_signature: &ID3D12RootSignature,
+        pipelines: &TexturePipelines,
+        draw_data: &DrawData,
+        settings: &mut FrameSettings,
+        mut stats: FrameStats,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+    ) -> FrameStats {
         self.setup_render_state(
             root_signature,
-            pipeline_state,
+            &pipelines.default,
             draw_data,
+            &settings.target,
             graphics_command_list,
         );
 
@@ -817,61 +2673,119 @@ impl RenderBuffers {
         let mut global_vtx_offset = 0;
         let mut global_idx_offset = 0;
 
-        for cmd_list in draw_data.draw_lists() {
-            for cmd in cmd_list.commands() {
+        let mut batcher = DrawBatcher::new(
+            graphics_command_list,
+            pipelines,
+            settings.merge_draw_calls,
+            settings.bindless_textures,
+            &mut stats,
+        );
+
+        for (draw_list_index, cmd_list) in draw_data.draw_lists().enumerate() {
+            #[cfg(feature = "pix")]
+            pix::begin_event(
+                graphics_command_list,
+                &pix::draw_list_event_name(cmd_list.raw()),
+            );
+
+            for (command_index, cmd) in cmd_list.commands().enumerate() {
                 match cmd {
                     DrawCmd::Elements { count, cmd_params } => {
                         // Project scissor/clipping rectangles into framebuffer space
-                        let clip_off = draw_data.display_pos;
-                        let clip_min = [
-                            cmd_params.clip_rect[0] - clip_off[0],
-                            cmd_params.clip_rect[1] - clip_off[1],
-                        ];
-                        let clip_max = [
-                            cmd_params.clip_rect[2] - clip_off[0],
-                            cmd_params.clip_rect[3] - clip_off[1],
-                        ];
-
-                        if clip_max[0] <= clip_min[0] || clip_max[1] <= clip_min[1] {
+                        let Some(r) = settings.target.project_clip_rect(cmd_params.clip_rect)
+                        else {
+                            batcher.stats.draw_calls_clipped += 1;
                             continue;
-                        }
-
-                        // Apply scissor/clipping rectangle, bind texture, Draw
-                        let r = RECT {
-                            left: clip_min[0] as i32,
-                            top: clip_min[1] as i32,
-                            right: clip_max[0] as i32,
-                            bottom: clip_max[1] as i32,
                         };
 
-                        let texture_handle = D3D12_GPU_DESCRIPTOR_HANDLE {
-                            ptr: cmd_params.texture_id.id() as u64,
+                        // Apply scissor/clipping rectangle, bind texture, Draw
+                        let texture_id = match settings.texture_validation.as_deref_mut() {
+                            Some(texture_validation) => texture_validation.validate(
+                                cmd_params.texture_id,
+                                draw_list_index,
+                                command_index,
+                            ),
+                            None => cmd_params.texture_id,
                         };
 
-                        graphics_command_list.SetGraphicsRootDescriptorTable(1, texture_handle);
-                        graphics_command_list.RSSetScissorRects(&[r]);
-                        graphics_command_list.DrawIndexedInstanced(
-                            count as u32,
-                            1,
-                            (cmd_params.idx_offset + global_idx_offset) as u32,
-                            (cmd_params.vtx_offset + global_vtx_offset) as i32,
-                            0,
+                        batcher.draw(DrawElements {
+                            texture: texture_id,
+                            scissor_rect: r,
+                            index_count: count as u32,
+                            start_index: (cmd_params.idx_offset + global_idx_offset) as u32,
+                            base_vertex: (cmd_params.vtx_offset + global_vtx_offset) as i32,
+                        });
+                    }
+                    DrawCmd::ResetRenderState => {
+                        batcher.flush();
+                        self.setup_render_state(
+                            root_signature,
+                            &pipelines.default,
+                            draw_data,
+                            &settings.target,
+                            graphics_command_list,
                         );
+                        batcher.invalidate_state();
                     }
-                    DrawCmd::ResetRenderState => self.setup_render_state(
-                        root_signature,
-                        pipeline_state,
-                        draw_data,
-                        graphics_command_list,
-                    ),
                     DrawCmd::RawCallback { callback, raw_cmd } => {
-                        callback(cmd_list.raw(), raw_cmd);
+                        batcher.flush();
+
+                        #[cfg(feature = "pix")]
+                        pix::begin_event(graphics_command_list, "imgui callback");
+
+                        if DrawCallbacks::is_draw_callback(callback) {
+                            // Like draw commands, callbacks added with
+                            // add_draw_callback are skipped when their clip
+                            // rectangle is empty
+                            if let Some(scissor_rect) = settings
+                                .target
+                                .project_clip_rect((*raw_cmd).ClipRect.into())
+                            {
+                                settings.draw_callbacks.call(
+                                    raw_cmd,
+                                    &DrawCallbackContext {
+                                        command_list: graphics_command_list,
+                                        scissor_rect,
+                                        viewport: settings.target.viewport(),
+                                        frame_index: settings.frame_index,
+                                    },
+                                );
+
+                                // Put back everything the callback may have changed
+                                self.setup_render_state(
+                                    root_signature,
+                                    &pipelines.default,
+                                    draw_data,
+                                    &settings.target,
+                                    graphics_command_list,
+                                );
+                                batcher.stats.callbacks += 1;
+                            }
+                        } else {
+                            callback(cmd_list.raw(), raw_cmd);
+                            batcher.stats.callbacks += 1;
+                        }
+
+                        #[cfg(feature = "pix")]
+                        pix::end_event(graphics_command_list);
+
+                        batcher.invalidate_state();
                     }
                 }
             }
+
+            // Flush before ending the draw list's event so that its last draw
+            // call is included in it
+            batcher.flush();
+
+            #[cfg(feature = "pix")]
+            pix::end_event(graphics_command_list);
+
             global_idx_offset += cmd_list.idx_buffer().len();
             global_vtx_offset += cmd_list.vtx_buffer().len();
         }
+
+        stats
     }
 
     unsafe fn setup_render_state(
@@ -879,6 +2793,7 @@ impl RenderBuffers {
         root_signature: &ID3D12RootSignature,
         pipeline_state: &ID3D12PipelineState,
         draw_data: &DrawData,
+        target: &TargetTransform,
         graphics_command_list: &ID3D12GraphicsCommandList,
     ) {
         #[repr(C)]
@@ -904,30 +2819,33 @@ impl RenderBuffers {
         };
 
         // Setup viewport
-        let vp = D3D12_VIEWPORT {
-            Width: draw_data.display_size[0],
-            Height: draw_data.display_size[1],
-            MinDepth: 0.0,
-            MaxDepth: 1.0,
-            ..Default::default()
-        };
         unsafe {
-            graphics_command_list.RSSetViewports(&[vp]);
+            graphics_command_list.RSSetViewports(&[target.viewport()]);
         }
 
         // Bind shader and vertex buffers
         let stride = std::mem::size_of::<DrawVert>();
         let vbv = D3D12_VERTEX_BUFFER_VIEW {
-            BufferLocation: self.vertex_buffer.as_ref().unwrap().GetGPUVirtualAddress(),
-            SizeInBytes: (self.vertex_buffer_size * stride) as u32,
+            BufferLocation: self
+                .vertex_buffer
+                .resource
+                .as_ref()
+                .unwrap()
+                .GetGPUVirtualAddress(),
+            SizeInBytes: (self.vertex_buffer.capacity * stride) as u32,
             StrideInBytes: stride as u32,
         };
         graphics_command_list.IASetVertexBuffers(0, Some(&[vbv]));
 
         let stride = std::mem::size_of::<DrawIdx>();
         let ibv = D3D12_INDEX_BUFFER_VIEW {
-            BufferLocation: self.index_buffer.as_ref().unwrap().GetGPUVirtualAddress(),
-            SizeInBytes: (self.index_buffer_size * stride) as u32,
+            BufferLocation: self
+                .index_buffer
+                .resource
+                .as_ref()
+                .unwrap()
+                .GetGPUVirtualAddress(),
+            SizeInBytes: (self.index_buffer.capacity * stride) as u32,
             Format: if stride == 2 {
                 DXGI_FORMAT_R16_UINT
             } else {
@@ -950,6 +2868,85 @@ impl RenderBuffers {
         graphics_command_list.OMSetBlendFactor(Some(&[0.0, 0.0, 0.0, 0.0]));
     }
 
+    fn map(resource: &ID3D12Resource) -> Result<*mut u8> {
+        let mut mapped = std::ptr::null_mut();
+        unsafe { resource.Map(0, None, Some(&mut mapped))? };
+        Ok(mapped as *mut u8)
+    }
+}
+
+enum BufferChange {
+    None,
+    Grown,
+    Shrunk,
+}
+
+impl UploadBuffer {
+    // Makes sure the buffer can hold `required` elements, reallocating it if
+    // it's too small, or if the policy says it has been too big for too long.
+    fn reserve(
+        &mut self,
+        device: &ID3D12Device,
+        kind: BufferKind,
+        required: usize,
+        policy: &BufferPolicy,
+        deletion_queue: &mut DeletionQueue,
+    ) -> Result<BufferChange> {
+        let growth = &policy.growth;
+
+        let (capacity, change) = if self.resource.is_none() || self.capacity < required {
+            (
+                growth.capacity_for(kind, required, self.capacity),
+                BufferChange::Grown,
+            )
+        } else if let Some(shrink_after_idle_frames) = policy.shrink_after_idle_frames {
+            if growth.capacity_for(kind, required, required) < self.capacity {
+                self.oversized_frames += 1;
+                self.oversized_peak = self.oversized_peak.max(required);
+            } else {
+                self.oversized_frames = 0;
+                self.oversized_peak = 0;
+            }
+
+            if self.oversized_frames < shrink_after_idle_frames {
+                return Ok(BufferChange::None);
+            }
+
+            (
+                growth.capacity_for(kind, self.oversized_peak, self.oversized_peak),
+                BufferChange::Shrunk,
+            )
+        } else {
+            return Ok(BufferChange::None);
+        };
+
+        let (element_size, name) = match kind {
+            BufferKind::Vertex => (std::mem::size_of::<DrawVert>(), "imgui VB"),
+            BufferKind::Index => (std::mem::size_of::<DrawIdx>(), "imgui IB"),
+        };
+
+        // Create the new buffer before giving up the old one, so that the old
+        // one is kept if this fails
+        let resource = Self::create_buffer(device, capacity * element_size)?;
+        unsafe {
+            resource.SetName(&HSTRING::from(format!(
+                "{} {}",
+                name, self.allocation_count
+            )))?;
+        }
+
+        // Frames in flight may still be reading the old buffer
+        if let Some(resource) = self.resource.replace(resource) {
+            deletion_queue.retire(resource);
+        }
+        self.capacity = capacity;
+        self.allocation_count += 1;
+        self.oversized_frames = 0;
+        self.oversized_peak = 0;
+
+        Ok(change)
+    }
+
     fn create_buffer(device: &ID3D12Device, width: usize) -> Result<ID3D12Resource> {
         let mut resource: Option<ID3D12Resource> = None;
 
@@ -981,10 +2978,618 @@ impl RenderBuffers {
 
         Ok(resource.unwrap())
     }
+}
 
-    unsafe fn map(resource: &ID3D12Resource) -> *mut u8 {
-        let mut mapped = std::ptr::null_mut();
-        resource.Map(0, None, Some(&mut mapped)).unwrap();
-        mapped as *mut u8
+#[derive(Clone, Copy)]
+struct DrawElements {
+    texture: TextureId,
+    scissor_rect: RECT,
+    index_count: u32,
+    start_index: u32,
+    base_vertex: i32,
+}
+
+// Issues draw calls, skipping state changes that wouldn't change anything and
+// optionally merging adjacent draws that can be drawn with a single call.
+struct DrawBatcher<'a> {
+    graphics_command_list: &'a ID3D12GraphicsCommandList,
+    pipelines: &'a TexturePipelines,
+    merge_draw_calls: bool,
+    bindless_textures: bool,
+    pipeline: Option<usize>,
+    texture: Option<TextureId>,
+    scissor_rect: Option<RECT>,
+    pending: Option<DrawElements>,
+    stats: &'a mut FrameStats,
+}
+
+impl<'a> DrawBatcher<'a> {
+    fn new(
+        graphics_command_list: &'a ID3D12GraphicsCommandList,
+        pipelines: &'a TexturePipelines,
+        merge_draw_calls: bool,
+        bindless_textures: bool,
+        stats: &'a mut FrameStats,
+    ) -> Self {
+        DrawBatcher {
+            graphics_command_list,
+            pipelines,
+            merge_draw_calls,
+            bindless_textures,
+            // setup_render_state has set the default pipeline state
+            pipeline: Some(0),
+            texture: None,
+            scissor_rect: None,
+            pending: None,
+            stats,
+        }
+    }
+
+    fn draw(&mut self, draw: DrawElements) {
+        if let Some(pending) = self.pending.as_mut() {
+            if pending.texture == draw.texture
+                && pending.scissor_rect == draw.scissor_rect
+                && pending.base_vertex == draw.base_vertex
+                && pending.start_index + pending.index_count == draw.start_index
+            {
+                pending.index_count += draw.index_count;
+                self.stats.merged_draw_calls += 1;
+                return;
+            }
+        }
+
+        self.flush();
+        self.pending = Some(draw);
+
+        if !self.merge_draw_calls {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let Some(draw) = self.pending.take() else {
+            return;
+        };
+
+        unsafe {
+            let pipeline = self.pipelines.index(draw.texture);
+            if self.pipeline != Some(pipeline) {
+                self.graphics_command_list
+                    .SetPipelineState(self.pipelines.get(pipeline));
+                self.pipeline = Some(pipeline);
+            }
+
+            if self.texture == Some(draw.texture) {
+                self.stats.texture_binds_skipped += 1;
+            } else {
+                if self.bindless_textures {
+                    self.graphics_command_list.SetGraphicsRoot32BitConstant(
+                        1,
+                        draw.texture.id() as u32,
+                        0,
+                    );
+                } else {
+                    self.graphics_command_list.SetGraphicsRootDescriptorTable(
+                        1,
+                        D3D12_GPU_DESCRIPTOR_HANDLE {
+                            ptr: draw.texture.id() as u64,
+                        },
+                    );
+                }
+                self.texture = Some(draw.texture);
+            }
+
+            if self.scissor_rect == Some(draw.scissor_rect) {
+                self.stats.scissor_rects_skipped += 1;
+            } else {
+                self.graphics_command_list
+                    .RSSetScissorRects(&[draw.scissor_rect]);
+                self.scissor_rect = Some(draw.scissor_rect);
+            }
+
+            self.graphics_command_list.DrawIndexedInstanced(
+                draw.index_count,
+                1,
+                draw.start_index,
+                draw.base_vertex,
+                0,
+            );
+        }
+
+        self.stats.draw_calls += 1;
+    }
+
+    // Forget what state has been set, for use after something else (such as a
+    // user callback) may have changed it.
+    fn invalidate_state(&mut self) {
+        self.pipeline = None;
+        self.texture = None;
+        self.scissor_rect = None;
+    }
+}
+
+// Maps imgui's display space onto the area of the render target that the UI is
+// drawn into.
+struct TargetTransform {
+    clip_off: [f32; 2],
+    clip_scale: [f32; 2],
+    // left, top, right, bottom in render target pixels
+    bounds: [f32; 4],
+}
+
+impl TargetTransform {
+    fn new(draw_data: &DrawData, target_rect: Option<RECT>) -> Option<Self> {
+        Self::from_display(
+            draw_data.display_pos,
+            draw_data.display_size,
+            draw_data.framebuffer_scale,
+            target_rect,
+        )
+    }
+
+    fn from_display(
+        display_pos: [f32; 2],
+        display_size: [f32; 2],
+        framebuffer_scale: [f32; 2],
+        target_rect: Option<RECT>,
+    ) -> Option<Self> {
+        let bounds = match target_rect {
+            Some(r) => [r.left as f32, r.top as f32, r.right as f32, r.bottom as f32],
+            None => [
+                0.0,
+                0.0,
+                display_size[0] * framebuffer_scale[0],
+                display_size[1] * framebuffer_scale[1],
+            ],
+        };
+
+        // Written this way round so that NaN bounds are rejected too
+        if !(bounds[2] > bounds[0] && bounds[3] > bounds[1]) {
+            return None;
+        }
+
+        Some(TargetTransform {
+            clip_off: display_pos,
+            clip_scale: [
+                (bounds[2] - bounds[0]) / display_size[0],
+                (bounds[3] - bounds[1]) / display_size[1],
+            ],
+            bounds,
+        })
+    }
+
+    fn viewport(&self) -> D3D12_VIEWPORT {
+        D3D12_VIEWPORT {
+            TopLeftX: self.bounds[0],
+            TopLeftY: self.bounds[1],
+            Width: self.bounds[2] - self.bounds[0],
+            Height: self.bounds[3] - self.bounds[1],
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        }
+    }
+
+    // Projects an imgui clip rectangle into render target space.  As in
+    // imgui_impl_dx12 the coordinates are truncated to integers, but only after
+    // being clamped to the target bounds so that off-screen, huge or NaN clip
+    // rectangles never reach RSSetScissorRects.  Returns None if the resulting
+    // rectangle has no area.
+    fn project_clip_rect(&self, clip_rect: [f32; 4]) -> Option<RECT> {
+        let project = |i: usize| {
+            let axis = i % 2;
+            let v =
+                (clip_rect[i] - self.clip_off[axis]) * self.clip_scale[axis] + self.bounds[axis];
+
+            // Unlike f32::clamp, max and min map NaN to the bound
+            v.max(self.bounds[axis]).min(self.bounds[axis + 2]) as i32
+        };
+
+        let r = RECT {
+            left: project(0),
+            top: project(1),
+            right: project(2),
+            bottom: project(3),
+        };
+
+        if r.right <= r.left || r.bottom <= r.top {
+            return None;
+        }
+
+        Some(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> RECT {
+        RECT {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    // A 400x300 display whose top left is at (100, 50), as it is for a
+    // secondary viewport.
+    fn offset_display(scale: f32) -> TargetTransform {
+        TargetTransform::from_display([100.0, 50.0], [400.0, 300.0], [scale, scale], None).unwrap()
+    }
+
+    #[test]
+    fn clip_rects_are_scaled_by_the_framebuffer_scale() {
+        for (scale, expected) in [
+            (1.0, rect(10, 10, 110, 110)),
+            (1.5, rect(15, 15, 165, 165)),
+            (2.0, rect(20, 20, 220, 221)),
+        ] {
+            let target = offset_display(scale);
+            assert_eq!(
+                target.project_clip_rect([110.0, 60.0, 210.0, 160.5]),
+                Some(expected),
+                "framebuffer_scale {scale}"
+            );
+        }
+    }
+
+    #[test]
+    fn clip_rects_are_clamped_to_the_scaled_display() {
+        for (scale, expected) in [
+            (1.0, rect(0, 0, 400, 300)),
+            (1.5, rect(0, 0, 600, 450)),
+            (2.0, rect(0, 0, 800, 600)),
+        ] {
+            let target = offset_display(scale);
+            assert_eq!(
+                target.project_clip_rect([0.0, 0.0, 1000.0, 1000.0]),
+                Some(expected),
+                "framebuffer_scale {scale}"
+            );
+        }
+    }
+
+    #[test]
+    fn clip_rects_outside_the_display_are_culled() {
+        for scale in [1.0, 1.5, 2.0] {
+            let target = offset_display(scale);
+            assert_eq!(target.project_clip_rect([0.0, 0.0, 100.0, 50.0]), None);
+            assert_eq!(target.project_clip_rect([500.0, 60.0, 600.0, 100.0]), None);
+        }
+    }
+
+    // A xorshift generator, so that the randomized tests are repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next_u32(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 32) as u32
+        }
+
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            min + (max - min) * (self.next_u32() as f32 / u32::MAX as f32)
+        }
+
+        // Mostly values in range, with the occasional one that isn't finite
+        // or is far outside it.
+        fn coordinate(&mut self, min: f32, max: f32) -> f32 {
+            match self.next_u32() % 32 {
+                0 => f32::NAN,
+                1 => f32::INFINITY,
+                2 => f32::NEG_INFINITY,
+                3 => 1e30,
+                4 => -1e30,
+                _ => self.range(min, max),
+            }
+        }
+
+        fn target(&mut self) -> TargetTransform {
+            let display_pos = [self.range(-500.0, 500.0), self.range(-500.0, 500.0)];
+            let display_size = [self.range(1.0, 2000.0), self.range(1.0, 2000.0)];
+            let scale = [1.0, 1.25, 1.5, 2.0, 3.0][self.next_u32() as usize % 5];
+
+            let target_rect = (self.next_u32() & 1 == 0).then(|| {
+                let left = self.range(-100.0, 1000.0) as i32;
+                let top = self.range(-100.0, 1000.0) as i32;
+                rect(
+                    left,
+                    top,
+                    left + self.range(1.0, 2000.0) as i32,
+                    top + self.range(1.0, 2000.0) as i32,
+                )
+            });
+
+            TargetTransform::from_display(display_pos, display_size, [scale, scale], target_rect)
+                .unwrap()
+        }
+
+        fn clip_rect(&mut self, target: &TargetTransform, finite: bool) -> [f32; 4] {
+            let extent = [
+                (target.bounds[2] - target.bounds[0]) / target.clip_scale[0],
+                (target.bounds[3] - target.bounds[1]) / target.clip_scale[1],
+            ];
+
+            std::array::from_fn(|i| {
+                let axis = i % 2;
+                let min = target.clip_off[axis] - extent[axis];
+                let max = target.clip_off[axis] + extent[axis] * 2.0;
+                match finite {
+                    true => self.range(min, max),
+                    false => self.coordinate(min, max),
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn projected_clip_rects_are_within_the_target_bounds() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..10_000 {
+            let target = rng.target();
+            let clip_rect = rng.clip_rect(&target, false);
+
+            if let Some(r) = target.project_clip_rect(clip_rect) {
+                let bounds = target.bounds.map(|bound| bound as i32);
+                assert!(
+                    r.left >= bounds[0]
+                        && r.top >= bounds[1]
+                        && r.right <= bounds[2]
+                        && r.bottom <= bounds[3],
+                    "{clip_rect:?} projected to {r:?}, outside {bounds:?}"
+                );
+                assert!(r.right > r.left && r.bottom > r.top);
+            }
+        }
+    }
+
+    #[test]
+    fn projected_clip_rects_match_imgui_impl_dx12() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+        for _ in 0..10_000 {
+            let target = rng.target();
+            let clip_rect = rng.clip_rect(&target, true);
+
+            // imgui_impl_dx12 truncates the projected rectangle to integers
+            // and skips it if it's empty.  Truncation is monotonic, so
+            // clamping before or after it gives the same result.
+            let project = |i: usize| {
+                let axis = i % 2;
+                let v = (clip_rect[i] - target.clip_off[axis]) * target.clip_scale[axis]
+                    + target.bounds[axis];
+                (v as i32).clamp(target.bounds[axis] as i32, target.bounds[axis + 2] as i32)
+            };
+            let expected = rect(project(0), project(1), project(2), project(3));
+            let empty = expected.right <= expected.left || expected.bottom <= expected.top;
+
+            assert_eq!(
+                target.project_clip_rect(clip_rect),
+                (!empty).then_some(expected),
+                "{clip_rect:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_clip_rects_are_culled() {
+        let mut rng = Rng(0xd1b5_4a32_d192_ed03);
+
+        for _ in 0..10_000 {
+            let target = rng.target();
+            let mut clip_rect = rng.clip_rect(&target, true);
+
+            // Make the rectangle empty along one or both axes
+            let empty_axes: &[usize] = match rng.next_u32() % 3 {
+                0 => &[0],
+                1 => &[1],
+                _ => &[0, 1],
+            };
+            for &axis in empty_axes {
+                if clip_rect[axis + 2] > clip_rect[axis] {
+                    clip_rect.swap(axis, axis + 2);
+                }
+            }
+
+            assert_eq!(target.project_clip_rect(clip_rect), None, "{clip_rect:?}");
+        }
+    }
+
+    #[test]
+    fn viewport_covers_the_scaled_display() {
+        let viewport = offset_display(1.5).viewport();
+        assert_eq!(
+            [
+                viewport.TopLeftX,
+                viewport.TopLeftY,
+                viewport.Width,
+                viewport.Height
+            ],
+            [0.0, 0.0, 600.0, 450.0]
+        );
+    }
+
+    #[test]
+    fn bindless_indices_are_only_given_to_descriptors_in_the_heap() {
+        let bindless_textures = BindlessTextures {
+            heap_start: D3D12_GPU_DESCRIPTOR_HANDLE { ptr: 0x1000 },
+            descriptor_size: 32,
+        };
+        let index = |ptr| bindless_textures.index(D3D12_GPU_DESCRIPTOR_HANDLE { ptr });
+
+        assert_eq!(index(0x1000), Some(0));
+        assert_eq!(index(0x1000 + 5 * 32), Some(5));
+        assert_eq!(index(0xfe0), None);
+        assert_eq!(index(0), None);
+        assert_eq!(index(0x1000 + (u32::MAX as u64 + 1) * 32), None);
+    }
+
+    // What GetCopyableFootprints reports for a 2D texture whose blocks are
+    // block_size texels square: each row of blocks is padded to a 256 byte
+    // pitch and each mip level starts on a 512 byte boundary.
+    fn texture_footprints(
+        width: u32,
+        height: u32,
+        mips: u32,
+        block_size: u32,
+        bytes_per_block: u64,
+    ) -> (Vec<SubresourceFootprint>, u64) {
+        let align = |value: u64, alignment: u64| value.div_ceil(alignment) * alignment;
+
+        let mut footprints = Vec::new();
+        let mut upload_size = 0;
+        for mip in 0..mips {
+            let width = (width >> mip).max(1).div_ceil(block_size);
+            let height = (height >> mip).max(1).div_ceil(block_size);
+            let row_size = width as u64 * bytes_per_block;
+            let footprint = SubresourceFootprint {
+                offset: align(upload_size, 512),
+                row_pitch: align(row_size, 256),
+                num_rows: height,
+                row_size,
+            };
+            upload_size = footprint.offset + footprint.row_pitch * (height as u64 - 1) + row_size;
+            footprints.push(footprint);
+        }
+
+        (footprints, upload_size)
+    }
+
+    // Uploads numbered bytes through the plan into a buffer filled with 0xff,
+    // and checks that each row landed where its footprint says while the
+    // padding was left alone.
+    fn check_upload(footprints: &[SubresourceFootprint], upload_size: u64, data_len: usize) {
+        let data: Vec<u8> = (0..data_len).map(|index| (index % 251) as u8).collect();
+        let copies = plan_texture_upload(footprints, upload_size, data.len()).unwrap();
+
+        let mut upload = vec![0xff; upload_size as usize];
+        for copy in &copies {
+            copy_rows(
+                &data[copy.src_range.clone()],
+                &mut upload[copy.dst_range.clone()],
+                copy.row_size,
+                copy.row_pitch,
+            );
+        }
+
+        let mut expected = vec![0xff; upload_size as usize];
+        let mut src = data.iter();
+        for footprint in footprints {
+            for row in 0..footprint.num_rows as u64 {
+                let start = (footprint.offset + row * footprint.row_pitch) as usize;
+                for byte in &mut expected[start..start + footprint.row_size as usize] {
+                    *byte = *src.next().unwrap();
+                }
+            }
+        }
+        assert_eq!(src.next(), None);
+        assert_eq!(upload, expected);
+    }
+
+    #[test]
+    fn unaligned_rgba8_rows_are_padded_to_the_row_pitch() {
+        for width in [1, 3, 5, 63, 65] {
+            let (footprints, upload_size) = texture_footprints(width, 3, 1, 1, 4);
+            let data_len = width as usize * 4 * 3;
+            check_upload(&footprints, upload_size, data_len);
+
+            let copies = plan_texture_upload(&footprints, upload_size, data_len).unwrap();
+            assert_eq!(copies[0].row_size, width as usize * 4);
+            assert_eq!(copies[0].row_pitch, if width < 65 { 256 } else { 512 });
+        }
+    }
+
+    #[test]
+    fn mip_chains_are_read_one_after_the_other() {
+        // 5x4, 2x2 and 1x1
+        let (footprints, upload_size) = texture_footprints(5, 4, 3, 1, 4);
+        assert_eq!(
+            footprints
+                .iter()
+                .map(|footprint| footprint.offset)
+                .collect::<Vec<_>>(),
+            [0, 1024, 1536]
+        );
+        check_upload(&footprints, upload_size, 80 + 16 + 4);
+
+        let copies = plan_texture_upload(&footprints, upload_size, 100).unwrap();
+        assert_eq!(
+            copies
+                .iter()
+                .map(|copy| copy.src_range.clone())
+                .collect::<Vec<_>>(),
+            [0..80, 80..96, 96..100]
+        );
+    }
+
+    #[test]
+    fn block_compressed_rows_are_rows_of_blocks() {
+        // BC1 is 8 bytes per 4x4 block.  10x6 has 3x2 blocks, then 5x3 has
+        // 2x1, and 2x1 and 1x1 have a single block each.
+        let (footprints, upload_size) = texture_footprints(10, 6, 4, 4, 8);
+        assert_eq!(
+            footprints
+                .iter()
+                .map(|footprint| (footprint.row_size, footprint.num_rows))
+                .collect::<Vec<_>>(),
+            [(24, 2), (16, 1), (8, 1), (8, 1)]
+        );
+        check_upload(&footprints, upload_size, 48 + 16 + 8 + 8);
+
+        // BC3 is 16 bytes per block
+        let (footprints, upload_size) = texture_footprints(8, 8, 1, 4, 16);
+        check_upload(&footprints, upload_size, 64);
+        assert!(plan_texture_upload(&footprints, upload_size, 8 * 8 * 4).is_err());
+    }
+
+    #[test]
+    fn texture_data_of_the_wrong_size_is_rejected() {
+        let (footprints, upload_size) = texture_footprints(3, 2, 1, 1, 4);
+        assert_eq!(
+            plan_texture_upload(&footprints, upload_size, 23),
+            Err("expected 24 bytes of texture data, got 23".to_string())
+        );
+        assert!(plan_texture_upload(&footprints, upload_size, 25).is_err());
+        assert!(plan_texture_upload(&footprints, upload_size, 24).is_ok());
+    }
+
+    #[test]
+    fn footprints_outside_the_upload_buffer_are_rejected() {
+        let (footprints, upload_size) = texture_footprints(3, 2, 1, 1, 4);
+        assert!(plan_texture_upload(&footprints, upload_size - 1, 24).is_err());
+
+        let overlapping = [SubresourceFootprint {
+            row_pitch: 8,
+            ..footprints[0]
+        }];
+        assert!(plan_texture_upload(&overlapping, upload_size, 24).is_err());
+    }
+
+    #[test]
+    fn copy_rows_leaves_the_padding_alone() {
+        let mut dst = [0xff; 10];
+        copy_rows(&[1, 2, 3, 4, 5, 6], &mut dst, 3, 4);
+        assert_eq!(dst, [1, 2, 3, 0xff, 4, 5, 6, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn copy_rows_stops_at_the_end_of_either_buffer() {
+        let mut dst = [0xff; 7];
+        copy_rows(&[1, 2, 3, 4, 5, 6, 7, 8, 9], &mut dst, 3, 4);
+        assert_eq!(dst, [1, 2, 3, 0xff, 4, 5, 6]);
+
+        let mut dst = [0xff; 4];
+        copy_rows(&[], &mut dst, 0, 4);
+        assert_eq!(dst, [0xff; 4]);
+    }
+
+    #[test]
+    fn renderer_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Renderer>();
     }
 }
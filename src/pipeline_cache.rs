@@ -0,0 +1,412 @@
+//
+// Pipeline state objects for each render target configuration the UI has been
+// drawn into, built on first use and optionally persisted in a pipeline
+// library.
+//
+
+use std::path::PathBuf;
+
+use windows::{
+    core::{Interface, Result, HSTRING},
+    Win32::{
+        Foundation::{FALSE, TRUE},
+        Graphics::{
+            Direct3D::ID3DBlob,
+            Direct3D12::{
+                ID3D12Device, ID3D12Device1, ID3D12PipelineLibrary, ID3D12PipelineState,
+                ID3D12RootSignature, D3D12_BLEND_DESC, D3D12_BLEND_INV_SRC_ALPHA, D3D12_BLEND_ONE,
+                D3D12_BLEND_OP_ADD, D3D12_BLEND_SRC_ALPHA, D3D12_COLOR_WRITE_ENABLE_ALL,
+                D3D12_COMPARISON_FUNC_ALWAYS, D3D12_CULL_MODE_NONE, D3D12_DEFAULT_DEPTH_BIAS,
+                D3D12_DEFAULT_DEPTH_BIAS_CLAMP, D3D12_DEFAULT_SLOPE_SCALED_DEPTH_BIAS,
+                D3D12_DEPTH_STENCILOP_DESC, D3D12_DEPTH_STENCIL_DESC, D3D12_DEPTH_WRITE_MASK_ALL,
+                D3D12_FILL_MODE_SOLID, D3D12_GRAPHICS_PIPELINE_STATE_DESC, D3D12_INPUT_LAYOUT_DESC,
+                D3D12_LOGIC_OP_NOOP, D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE, D3D12_RASTERIZER_DESC,
+                D3D12_RENDER_TARGET_BLEND_DESC, D3D12_SHADER_BYTECODE, D3D12_STENCIL_OP_KEEP,
+            },
+            Dxgi::Common::{DXGI_FORMAT, DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
+        },
+    },
+};
+
+use crate::{DeviceObjects, ShaderId};
+
+/// How the UI is blended into the render target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Standard alpha blending, as expected by ImGui's default colors.
+    #[default]
+    Alpha,
+
+    /// Blending for colors that are already multiplied by their alpha.  The
+    /// built-in pixel shader outputs straight alpha, so this is only for custom
+    /// pixel shaders that premultiply their output.  `Alpha` already leaves a
+    /// premultiplied result in a render target that started out with one.
+    PremultipliedAlpha,
+
+    /// No blending; the UI overwrites the render target.
+    Opaque,
+}
+
+/// Describes the render target the UI is drawn into.  A pipeline state is
+/// created for each distinct description the first time it's used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderTargetDesc {
+    /// The format of the render target view.
+    pub rtv_format: DXGI_FORMAT,
+
+    /// The render target's sample count.
+    pub sample_count: u32,
+
+    /// The format of the bound depth stencil view, or `DXGI_FORMAT_UNKNOWN`
+    /// if there isn't one.  The UI doesn't use depth testing.
+    pub dsv_format: DXGI_FORMAT,
+
+    /// How the UI is blended into the render target.
+    pub blend_mode: BlendMode,
+}
+
+impl RenderTargetDesc {
+    /// A single sampled render target of the given format, with no depth
+    /// stencil view and alpha blending.
+    pub fn new(rtv_format: DXGI_FORMAT) -> Self {
+        RenderTargetDesc {
+            rtv_format,
+            sample_count: 1,
+            dsv_format: DXGI_FORMAT_UNKNOWN,
+            blend_mode: BlendMode::Alpha,
+        }
+    }
+}
+
+/// Where a pipeline library's contents are loaded from.
+#[derive(Clone, Debug)]
+pub enum PipelineLibrarySource {
+    /// A file written by [`crate::Renderer::save_pipeline_library`].  If it
+    /// doesn't exist yet the library starts empty.
+    File(PathBuf),
+
+    /// Data previously returned by
+    /// [`crate::Renderer::serialize_pipeline_library`].
+    Memory(Vec<u8>),
+}
+
+impl PipelineLibrarySource {
+    fn load(&self) -> Vec<u8> {
+        match self {
+            // A missing or unreadable file just means starting from scratch
+            PipelineLibrarySource::File(path) => std::fs::read(path).unwrap_or_default(),
+            PipelineLibrarySource::Memory(data) => data.clone(),
+        }
+    }
+}
+
+pub(crate) struct PipelineCache {
+    vertex_shader: ID3DBlob,
+    pixel_shader: ID3DBlob,
+    // Distinguishes pipeline states built from different shaders in the
+    // pipeline library
+    shader_variant: &'static str,
+    pipeline_states: Vec<CachedPipelineState>,
+    library: Option<PipelineLibrary>,
+}
+
+struct CachedPipelineState {
+    target: RenderTargetDesc,
+    // None for the built-in pixel shader
+    pixel_shader: Option<ShaderId>,
+    name: HSTRING,
+    // Failures are cached too, so that a bad configuration isn't retried
+    // every frame
+    pipeline_state: Result<ID3D12PipelineState>,
+}
+
+pub(crate) struct PipelineLibrary {
+    library: ID3D12PipelineLibrary,
+    _data: Vec<u8>, // the library reads from this for as long as it exists
+}
+
+impl PipelineCache {
+    pub(crate) fn new(
+        vertex_shader: ID3DBlob,
+        pixel_shader: ID3DBlob,
+        shader_variant: &'static str,
+    ) -> Self {
+        PipelineCache {
+            vertex_shader,
+            pixel_shader,
+            shader_variant,
+            pipeline_states: Vec::new(),
+            library: None,
+        }
+    }
+
+    pub(crate) fn library(&self) -> Option<&PipelineLibrary> {
+        self.library.as_ref()
+    }
+
+    // Pipeline states that were created before the library was set are
+    // stored in it, so that they're saved too.
+    pub(crate) fn set_library(&mut self, library: Option<PipelineLibrary>) {
+        if let Some(library) = library.as_ref() {
+            for cached in &self.pipeline_states {
+                if let Ok(pipeline_state) = &cached.pipeline_state {
+                    library.store(&cached.name, pipeline_state);
+                }
+            }
+        }
+
+        self.library = library;
+    }
+
+    // Only a handful of configurations are expected, so a linear search is
+    // fine.  `pixel_shader` replaces the built-in pixel shader.
+    pub(crate) fn get_or_create(
+        &mut self,
+        device: &ID3D12Device,
+        root_signature: &ID3D12RootSignature,
+        target: &RenderTargetDesc,
+        pixel_shader: Option<(ShaderId, &ID3DBlob)>,
+    ) -> Result<ID3D12PipelineState> {
+        let shader_id = pixel_shader.map(|(id, _)| id);
+        if let Some(cached) = self
+            .pipeline_states
+            .iter()
+            .find(|cached| cached.target == *target && cached.pixel_shader == shader_id)
+        {
+            return cached.pipeline_state.clone();
+        }
+
+        let pixel_shader = pixel_shader.map(|(_, shader)| shader);
+        let name = self.pipeline_state_name(target, pixel_shader);
+        let pipeline_state =
+            self.create_pipeline_state(device, root_signature, target, pixel_shader, &name);
+        self.pipeline_states.push(CachedPipelineState {
+            target: *target,
+            pixel_shader: shader_id,
+            name,
+            pipeline_state: pipeline_state.clone(),
+        });
+
+        pipeline_state
+    }
+
+    // Removes the pipeline states built from a custom pixel shader, for when
+    // it is unregistered or recompiled, and returns them so they can be kept
+    // alive until the GPU has finished with them.
+    pub(crate) fn remove_pixel_shader(&mut self, id: ShaderId) -> Vec<ID3D12PipelineState> {
+        let (removed, kept) = std::mem::take(&mut self.pipeline_states)
+            .into_iter()
+            .partition(|cached| cached.pixel_shader == Some(id));
+        self.pipeline_states = kept;
+
+        removed
+            .into_iter()
+            .filter_map(|cached: CachedPipelineState| cached.pipeline_state.ok())
+            .collect()
+    }
+
+    // Names include the crate version and hashes of the shaders' bytecode, so
+    // that pipeline states built with other shaders or compile options aren't
+    // loaded.
+    fn pipeline_state_name(
+        &self,
+        target: &RenderTargetDesc,
+        pixel_shader: Option<&ID3DBlob>,
+    ) -> HSTRING {
+        HSTRING::from(format!(
+            "imgui-windows-d3d12-renderer {} {} vs:{:016x} ps:{:016x} rtv:{} samples:{} dsv:{} blend:{:?}",
+            env!("CARGO_PKG_VERSION"),
+            self.shader_variant,
+            bytecode_hash(&self.vertex_shader),
+            bytecode_hash(pixel_shader.unwrap_or(&self.pixel_shader)),
+            target.rtv_format.0,
+            target.sample_count,
+            target.dsv_format.0,
+            target.blend_mode
+        ))
+    }
+
+    fn create_pipeline_state(
+        &self,
+        device: &ID3D12Device,
+        root_signature: &ID3D12RootSignature,
+        target: &RenderTargetDesc,
+        pixel_shader: Option<&ID3DBlob>,
+        name: &HSTRING,
+    ) -> Result<ID3D12PipelineState> {
+        let shader_bytecode = |shader: &ID3DBlob| unsafe {
+            D3D12_SHADER_BYTECODE {
+                pShaderBytecode: shader.GetBufferPointer(),
+                BytecodeLength: shader.GetBufferSize(),
+            }
+        };
+
+        let default_stencilop = D3D12_DEPTH_STENCILOP_DESC {
+            StencilFailOp: D3D12_STENCIL_OP_KEEP,
+            StencilDepthFailOp: D3D12_STENCIL_OP_KEEP,
+            StencilPassOp: D3D12_STENCIL_OP_KEEP,
+            StencilFunc: D3D12_COMPARISON_FUNC_ALWAYS,
+        };
+
+        let input_layout = DeviceObjects::input_layout();
+
+        let mut desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+            pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
+            VS: shader_bytecode(&self.vertex_shader),
+            PS: shader_bytecode(pixel_shader.unwrap_or(&self.pixel_shader)),
+            BlendState: D3D12_BLEND_DESC {
+                AlphaToCoverageEnable: false.into(),
+                IndependentBlendEnable: false.into(),
+                RenderTarget: [
+                    render_target_blend_desc(target.blend_mode),
+                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                    D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                ],
+            },
+            SampleMask: u32::MAX,
+            RasterizerState: D3D12_RASTERIZER_DESC {
+                FillMode: D3D12_FILL_MODE_SOLID,
+                CullMode: D3D12_CULL_MODE_NONE,
+                DepthBias: D3D12_DEFAULT_DEPTH_BIAS,
+                DepthBiasClamp: D3D12_DEFAULT_DEPTH_BIAS_CLAMP,
+                SlopeScaledDepthBias: D3D12_DEFAULT_SLOPE_SCALED_DEPTH_BIAS,
+                DepthClipEnable: TRUE,
+                ..Default::default()
+            },
+            DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+                DepthEnable: FALSE,
+                DepthWriteMask: D3D12_DEPTH_WRITE_MASK_ALL,
+                DepthFunc: D3D12_COMPARISON_FUNC_ALWAYS,
+                StencilEnable: FALSE,
+                FrontFace: default_stencilop,
+                BackFace: default_stencilop,
+                ..Default::default()
+            },
+            InputLayout: D3D12_INPUT_LAYOUT_DESC {
+                pInputElementDescs: input_layout.as_ptr(),
+                NumElements: input_layout.len() as u32,
+            },
+            PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
+            NumRenderTargets: 1,
+            DSVFormat: target.dsv_format,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: target.sample_count,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        desc.RTVFormats[0] = target.rtv_format;
+
+        match self.library.as_ref() {
+            Some(library) => library.load_or_create(device, name, &desc),
+            None => unsafe { device.CreateGraphicsPipelineState(&desc) },
+        }
+    }
+}
+
+impl PipelineLibrary {
+    // Returns None if the device doesn't support pipeline libraries.
+    pub(crate) fn new(device: &ID3D12Device, source: &PipelineLibrarySource) -> Option<Self> {
+        let device: ID3D12Device1 = device.cast().ok()?;
+
+        let mut data = source.load();
+        let library = unsafe { device.CreatePipelineLibrary(&data) }
+            .or_else(|_| {
+                // The data was written by a different driver or adapter, or is
+                // corrupt, so start again with an empty library
+                data = Vec::new();
+                unsafe { device.CreatePipelineLibrary(&data) }
+            })
+            .ok()?;
+
+        Some(PipelineLibrary {
+            library,
+            _data: data,
+        })
+    }
+
+    pub(crate) fn serialize(&self) -> Result<Vec<u8>> {
+        unsafe {
+            let mut data = vec![0; self.library.GetSerializedSize()];
+            self.library.Serialize(&mut data)?;
+            Ok(data)
+        }
+    }
+
+    fn load_or_create(
+        &self,
+        device: &ID3D12Device,
+        name: &HSTRING,
+        desc: &D3D12_GRAPHICS_PIPELINE_STATE_DESC,
+    ) -> Result<ID3D12PipelineState> {
+        if let Ok(pipeline_state) = unsafe { self.library.LoadGraphicsPipeline(name, desc) } {
+            return Ok(pipeline_state);
+        }
+
+        let pipeline_state = unsafe { device.CreateGraphicsPipelineState(desc) }?;
+        self.store(name, &pipeline_state);
+
+        Ok(pipeline_state)
+    }
+
+    // Storing only fails if the library already has a pipeline state with this
+    // name, which doesn't stop the pipeline state being used.
+    fn store(&self, name: &HSTRING, pipeline_state: &ID3D12PipelineState) {
+        let _ = unsafe { self.library.StorePipeline(name, pipeline_state) };
+    }
+}
+
+fn render_target_blend_desc(blend_mode: BlendMode) -> D3D12_RENDER_TARGET_BLEND_DESC {
+    let src_blend = match blend_mode {
+        BlendMode::Alpha => D3D12_BLEND_SRC_ALPHA,
+        BlendMode::PremultipliedAlpha | BlendMode::Opaque => D3D12_BLEND_ONE,
+    };
+
+    D3D12_RENDER_TARGET_BLEND_DESC {
+        BlendEnable: (blend_mode != BlendMode::Opaque).into(),
+        LogicOpEnable: false.into(),
+        SrcBlend: src_blend,
+        DestBlend: D3D12_BLEND_INV_SRC_ALPHA,
+        BlendOp: D3D12_BLEND_OP_ADD,
+        SrcBlendAlpha: D3D12_BLEND_ONE,
+        DestBlendAlpha: D3D12_BLEND_INV_SRC_ALPHA,
+        BlendOpAlpha: D3D12_BLEND_OP_ADD,
+        LogicOp: D3D12_LOGIC_OP_NOOP,
+        RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
+    }
+}
+
+// The hash is part of names stored in pipeline libraries that outlive the
+// process, so it must not change between runs or Rust versions.
+fn bytecode_hash(shader: &ID3DBlob) -> u64 {
+    fnv1a(unsafe {
+        std::slice::from_raw_parts(
+            shader.GetBufferPointer() as *const u8,
+            shader.GetBufferSize(),
+        )
+    })
+}
+
+// 64-bit FNV-1a.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
@@ -0,0 +1,100 @@
+//
+// Debug event markers, so that the renderer's work shows up as named regions in
+// GPU captures from PIX, RenderDoc and similar tools.
+//
+
+use std::ffi::{c_void, CStr};
+
+use windows::Win32::Graphics::Direct3D12::ID3D12GraphicsCommandList;
+
+// PIX's WINPIX_EVENT_UNICODE_VERSION: the event data is a null terminated UTF-16
+// string.
+const EVENT_UNICODE_VERSION: u32 = 0;
+
+pub(crate) unsafe fn begin_event(graphics_command_list: &ID3D12GraphicsCommandList, name: &str) {
+    let data = encode_event_name(name);
+    graphics_command_list.BeginEvent(
+        EVENT_UNICODE_VERSION,
+        Some(data.as_ptr() as *const c_void),
+        std::mem::size_of_val(data.as_slice()) as u32,
+    );
+}
+
+pub(crate) unsafe fn end_event(graphics_command_list: &ID3D12GraphicsCommandList) {
+    graphics_command_list.EndEvent();
+}
+
+// Encodes an event name in the form expected for EVENT_UNICODE_VERSION events.
+pub(crate) fn encode_event_name(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+// Names a draw list after the window that owns it, if it has one.
+pub(crate) fn draw_list_event_name(draw_list: &imgui::sys::ImDrawList) -> String {
+    let owner_name = if draw_list._OwnerName.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(draw_list._OwnerName) }.to_string_lossy())
+    };
+
+    format_draw_list_event_name(owner_name.as_deref())
+}
+
+// Window names may include a "##" suffix that is only used to build the
+// window's ID, so this is stripped.
+pub(crate) fn format_draw_list_event_name(owner_name: Option<&str>) -> String {
+    match owner_name.map(|name| name.split("##").next().unwrap_or_default()) {
+        Some(name) if !name.is_empty() => format!("imgui draw list: {}", name),
+        _ => "imgui draw list".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_names_are_null_terminated_utf16() {
+        assert_eq!(
+            encode_event_name("imgui"),
+            [0x69, 0x6d, 0x67, 0x75, 0x69, 0]
+        );
+        assert_eq!(encode_event_name(""), [0]);
+
+        // Characters outside the basic multilingual plane take two code units
+        assert_eq!(
+            encode_event_name("\u{e9}\u{1f600}"),
+            [0xe9, 0xd83d, 0xde00, 0]
+        );
+    }
+
+    #[test]
+    fn draw_lists_are_named_after_their_window() {
+        assert_eq!(
+            format_draw_list_event_name(Some("Debug")),
+            "imgui draw list: Debug"
+        );
+        assert_eq!(
+            format_draw_list_event_name(Some("Debug##2")),
+            "imgui draw list: Debug"
+        );
+        assert_eq!(
+            format_draw_list_event_name(Some("##hidden")),
+            "imgui draw list"
+        );
+        assert_eq!(format_draw_list_event_name(Some("")), "imgui draw list");
+        assert_eq!(format_draw_list_event_name(None), "imgui draw list");
+    }
+
+    #[test]
+    fn draw_list_owner_names_are_read_from_the_draw_list() {
+        let mut draw_list: imgui::sys::ImDrawList = unsafe { std::mem::zeroed() };
+        assert_eq!(draw_list_event_name(&draw_list), "imgui draw list");
+
+        draw_list._OwnerName = b"Textures##inspector\0".as_ptr() as *const _;
+        assert_eq!(
+            draw_list_event_name(&draw_list),
+            "imgui draw list: Textures"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//
+// Records the UI inside a render pass, which lets tile based GPUs avoid
+// loading or storing the render target more than they need to.
+//
+
+use windows::{
+    core::Interface,
+    Win32::Graphics::{
+        Direct3D12::{
+            ID3D12GraphicsCommandList, ID3D12GraphicsCommandList4, D3D12_CLEAR_VALUE,
+            D3D12_CLEAR_VALUE_0, D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_RENDER_PASS_BEGINNING_ACCESS,
+            D3D12_RENDER_PASS_BEGINNING_ACCESS_0,
+            D3D12_RENDER_PASS_BEGINNING_ACCESS_CLEAR_PARAMETERS,
+            D3D12_RENDER_PASS_BEGINNING_ACCESS_TYPE_CLEAR,
+            D3D12_RENDER_PASS_BEGINNING_ACCESS_TYPE_PRESERVE, D3D12_RENDER_PASS_ENDING_ACCESS,
+            D3D12_RENDER_PASS_ENDING_ACCESS_TYPE_PRESERVE, D3D12_RENDER_PASS_FLAG_NONE,
+            D3D12_RENDER_PASS_RENDER_TARGET_DESC,
+        },
+        Dxgi::Common::DXGI_FORMAT,
+    },
+};
+
+/// A render target for [`crate::Renderer::render_draw_data_with_options`] to
+/// bind itself and draw into inside a render pass.  The pass has no depth
+/// stencil view, so the render target's `dsv_format` should be
+/// `DXGI_FORMAT_UNKNOWN`.
+///
+/// The target must be in `D3D12_RESOURCE_STATE_RENDER_TARGET`.  Draw
+/// callbacks are called inside the pass, so they mustn't record barriers or
+/// copies.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderPass {
+    pub rtv: D3D12_CPU_DESCRIPTOR_HANDLE,
+    pub load_op: RenderPassLoadOp,
+}
+
+/// What a render pass does with the render target's contents before the UI is
+/// drawn.  They're always preserved afterwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderPassLoadOp {
+    /// Keep the contents, for drawing the UI over a scene.
+    Preserve,
+
+    /// Clear to the given color.
+    Clear([f32; 4]),
+}
+
+// A render pass that has been begun.  Command lists that don't support render
+// passes have the target bound, and cleared, instead.
+pub(crate) struct ActiveRenderPass {
+    command_list: Option<ID3D12GraphicsCommandList4>,
+}
+
+pub(crate) fn begin_render_pass(
+    graphics_command_list: &ID3D12GraphicsCommandList,
+    render_pass: &RenderPass,
+    rtv_format: DXGI_FORMAT,
+) -> ActiveRenderPass {
+    let Ok(command_list) = graphics_command_list.cast::<ID3D12GraphicsCommandList4>() else {
+        unsafe {
+            graphics_command_list.OMSetRenderTargets(1, Some(&render_pass.rtv), false, None);
+            if let RenderPassLoadOp::Clear(color) = render_pass.load_op {
+                graphics_command_list.ClearRenderTargetView(render_pass.rtv, &color, None);
+            }
+        }
+        return ActiveRenderPass { command_list: None };
+    };
+
+    let beginning_access = match render_pass.load_op {
+        RenderPassLoadOp::Preserve => D3D12_RENDER_PASS_BEGINNING_ACCESS {
+            Type: D3D12_RENDER_PASS_BEGINNING_ACCESS_TYPE_PRESERVE,
+            ..Default::default()
+        },
+        RenderPassLoadOp::Clear(color) => D3D12_RENDER_PASS_BEGINNING_ACCESS {
+            Type: D3D12_RENDER_PASS_BEGINNING_ACCESS_TYPE_CLEAR,
+            Anonymous: D3D12_RENDER_PASS_BEGINNING_ACCESS_0 {
+                Clear: D3D12_RENDER_PASS_BEGINNING_ACCESS_CLEAR_PARAMETERS {
+                    ClearValue: D3D12_CLEAR_VALUE {
+                        Format: rtv_format,
+                        Anonymous: D3D12_CLEAR_VALUE_0 { Color: color },
+                    },
+                },
+            },
+        },
+    };
+
+    let render_target = D3D12_RENDER_PASS_RENDER_TARGET_DESC {
+        cpuDescriptor: render_pass.rtv,
+        BeginningAccess: beginning_access,
+        EndingAccess: D3D12_RENDER_PASS_ENDING_ACCESS {
+            Type: D3D12_RENDER_PASS_ENDING_ACCESS_TYPE_PRESERVE,
+            ..Default::default()
+        },
+    };
+
+    unsafe {
+        command_list.BeginRenderPass(Some(&[render_target]), None, D3D12_RENDER_PASS_FLAG_NONE)
+    };
+
+    ActiveRenderPass {
+        command_list: Some(command_list),
+    }
+}
+
+impl ActiveRenderPass {
+    pub(crate) fn end(self) {
+        if let Some(command_list) = self.command_list {
+            unsafe { command_list.EndRenderPass() };
+        }
+    }
+}
@@ -0,0 +1,436 @@
+//
+// Configurable compilation of the renderer's built-in shaders and of custom
+// pixel shaders registered by the app.
+//
+
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+use imgui::TextureId;
+use windows::{
+    core::{Error, Result},
+    Win32::{
+        Foundation::E_INVALIDARG,
+        Graphics::{
+            Direct3D::{Fxc::D3DCreateBlob, ID3DBlob},
+            Direct3D12::{D3D_SHADER_MODEL, D3D_SHADER_MODEL_6_0, D3D_SHADER_MODEL_6_6},
+        },
+    },
+};
+
+use crate::{dxc, fxc};
+
+/// Which compiler the renderer uses for its shaders.
+#[derive(Clone, Debug, Default)]
+pub enum ShaderCompiler {
+    /// The legacy FXC compiler, targeting shader model 5.1.
+    #[default]
+    Fxc,
+
+    /// DXC, loaded from dxcompiler.dll, targeting shader model 6.0 unless
+    /// [`ShaderCompileOptions::dxc_shader_model`] says otherwise.
+    Dxc,
+
+    /// Use precompiled bytecode for the built-in shaders.  Custom shaders must
+    /// then be registered as [`ShaderSource::Bytecode`].  Texture array views
+    /// and the texture inspector's point sampling are drawn with HLSL shaders
+    /// of the renderer's own, so in this mode
+    /// [`crate::Renderer::register_texture_view`] fails for texture arrays,
+    /// and the inspector can't point sample or show a single slice, mip or
+    /// channel of one.
+    Precompiled(PrecompiledShaders),
+}
+
+/// Bytecode for the renderer's built-in shaders.  If bindless textures are
+/// enabled the pixel shader must read its texture through
+/// `ResourceDescriptorHeap`.
+#[derive(Clone, Debug)]
+pub struct PrecompiledShaders {
+    /// Bytecode for the vertex shader, which takes ImGui's vertices and the
+    /// projection matrix in constant buffer `b0`.
+    pub vertex_shader: Vec<u8>,
+
+    /// Bytecode for the pixel shader, which has the inputs described for
+    /// [`ShaderSource`].
+    pub pixel_shader: Vec<u8>,
+}
+
+/// Options passed to the shader compiler for both the built-in and custom
+/// shaders.
+#[derive(Clone, Default)]
+pub struct ShaderCompileOptions {
+    pub compiler: ShaderCompiler,
+
+    /// Preprocessor definitions, as (name, value) pairs.
+    pub defines: Vec<(String, String)>,
+
+    /// `D3DCOMPILE_*` flags used when compiling with FXC.
+    pub fxc_flags: u32,
+
+    /// Extra command line arguments used when compiling with DXC, such as
+    /// `-O3` or `-Zi`.
+    pub dxc_arguments: Vec<String>,
+
+    /// The shader model DXC compiles for, 6.0 if `None`.  Shaders for bindless
+    /// textures are compiled for at least 6.6 regardless.
+    pub dxc_shader_model: Option<D3D_SHADER_MODEL>,
+
+    /// Resolves `#include` directives.  Without one, shaders can't include
+    /// other files.
+    pub include_handler: Option<Arc<dyn ShaderIncludeHandler>>,
+}
+
+/// Resolves `#include` directives in shaders.
+pub trait ShaderIncludeHandler: Send + Sync {
+    /// Returns the contents of the named file, or `None` if it can't be found.
+    fn load(&self, file_name: &str) -> Option<Vec<u8>>;
+}
+
+/// A custom pixel shader registered with
+/// [`crate::Renderer::register_pixel_shader`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderId(pub(crate) usize);
+
+/// The source of a custom pixel shader.
+///
+/// HLSL shaders are given the same inputs as the built-in pixel shader:
+///
+/// ```hlsl
+/// struct PS_INPUT {
+///     float4 pos: SV_POSITION;
+///     float4 col: COLOR0;
+///     float2 uv: TEXCOORD0;
+/// };
+/// ```
+///
+/// The draw's texture is `Texture2D texture0` (`t0`) and the sampler is
+/// `sampler0` (`s0`).  With bindless textures enabled the texture's heap index
+/// is instead `uint textureIndex` in constant buffer `b1`, and HLSL shaders
+/// are compiled with `IMGUI_BINDLESS` defined.
+///
+/// In debug builds shaders loaded from files are recompiled when the file
+/// changes (see [`crate::Renderer::shader_reload_errors`]).
+#[derive(Clone, Debug)]
+pub enum ShaderSource {
+    Hlsl { source: String, entry_point: String },
+    HlslFile { path: PathBuf, entry_point: String },
+    Bytecode(Vec<u8>),
+}
+
+/// A shader file that changed but failed to compile, or compiled to bytecode
+/// that a pipeline state couldn't be created with.  The shader keeps using its
+/// previous bytecode.
+#[derive(Clone, Debug)]
+pub struct ShaderReloadError {
+    /// The shader whose file changed.
+    pub shader: ShaderId,
+
+    /// The shader's file.
+    pub path: PathBuf,
+
+    /// The compiler's or the device's error message.
+    pub message: String,
+}
+
+// Defined when compiling HLSL for bindless textures.
+const BINDLESS_TEXTURES_DEFINE: &str = "IMGUI_BINDLESS";
+
+#[derive(Clone, Copy)]
+pub(crate) enum ShaderStage {
+    Vertex,
+    Pixel,
+}
+
+// The custom pixel shaders registered with the renderer, and the textures that
+// are drawn with them.  Shaders are compiled when they're registered, and
+// again whenever the compile options change.
+#[derive(Default)]
+pub(crate) struct CustomShaders {
+    // Indexed by ShaderId
+    shaders: Vec<Option<CustomShader>>,
+    texture_shaders: Vec<(TextureId, ShaderId)>,
+    reload_errors: Vec<ShaderReloadError>,
+}
+
+pub(crate) struct CustomShader {
+    source: ShaderSource,
+    pub(crate) bytecode: ID3DBlob,
+    // When the source file was last compiled, for HlslFile sources
+    modified: Option<SystemTime>,
+}
+
+impl CustomShaders {
+    pub(crate) fn insert(&mut self, source: ShaderSource, bytecode: ID3DBlob) -> ShaderId {
+        let shader = Some(CustomShader {
+            modified: source.modified(),
+            source,
+            bytecode,
+        });
+
+        match self.shaders.iter().position(Option::is_none) {
+            Some(index) => {
+                self.shaders[index] = shader;
+                ShaderId(index)
+            }
+            None => {
+                self.shaders.push(shader);
+                ShaderId(self.shaders.len() - 1)
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: ShaderId) {
+        if let Some(shader) = self.shaders.get_mut(id.0) {
+            *shader = None;
+        }
+        self.texture_shaders.retain(|(_, shader)| *shader != id);
+        self.reload_errors.retain(|error| error.shader != id);
+    }
+
+    pub(crate) fn get(&self, id: ShaderId) -> Option<&CustomShader> {
+        self.shaders.get(id.0).and_then(Option::as_ref)
+    }
+
+    pub(crate) fn set_texture_shader(&mut self, texture_id: TextureId, shader: Option<ShaderId>) {
+        self.texture_shaders.retain(|(id, _)| *id != texture_id);
+        if let Some(shader) = shader {
+            self.texture_shaders.push((texture_id, shader));
+        }
+    }
+
+    pub(crate) fn clear_texture_shaders(&mut self) {
+        self.texture_shaders.clear();
+    }
+
+    pub(crate) fn texture_shaders(&self) -> &[(TextureId, ShaderId)] {
+        &self.texture_shaders
+    }
+
+    // Either every shader is recompiled, or none are.
+    pub(crate) fn recompile(
+        &mut self,
+        options: &ShaderCompileOptions,
+        bindless_textures: bool,
+    ) -> Result<()> {
+        let bytecode = self.compile_all(options, bindless_textures)?;
+        self.replace_bytecode(bytecode);
+        Ok(())
+    }
+
+    // Compiles every shader without changing any of them, so that the caller
+    // can finish whatever else may fail before passing the result to
+    // replace_bytecode.
+    pub(crate) fn compile_all(
+        &self,
+        options: &ShaderCompileOptions,
+        bindless_textures: bool,
+    ) -> Result<Vec<Option<ID3DBlob>>> {
+        self.shaders
+            .iter()
+            .map(|shader| {
+                shader
+                    .as_ref()
+                    .map(|shader| {
+                        options.compile(ShaderStage::Pixel, &shader.source, bindless_textures)
+                    })
+                    .transpose()
+            })
+            .collect()
+    }
+
+    pub(crate) fn replace_bytecode(&mut self, bytecode: Vec<Option<ID3DBlob>>) {
+        for (shader, bytecode) in self.shaders.iter_mut().zip(bytecode) {
+            if let (Some(shader), Some(bytecode)) = (shader.as_mut(), bytecode) {
+                shader.modified = shader.source.modified();
+                shader.bytecode = bytecode;
+            }
+        }
+        self.reload_errors.clear();
+    }
+
+    // Recompiles the shaders whose source files have changed since they were
+    // last compiled, returning the ones whose bytecode was replaced along with
+    // their previous bytecode.  Shaders that fail to compile keep their old
+    // bytecode and the failure is recorded in reload_errors.
+    pub(crate) fn reload_modified(
+        &mut self,
+        options: &ShaderCompileOptions,
+        bindless_textures: bool,
+    ) -> Vec<(ShaderId, ID3DBlob)> {
+        let mut reloaded = Vec::new();
+
+        for (index, shader) in self.shaders.iter_mut().enumerate() {
+            let Some(shader) = shader.as_mut() else {
+                continue;
+            };
+            let ShaderSource::HlslFile { path, .. } = &shader.source else {
+                continue;
+            };
+
+            let modified = shader.source.modified();
+            if modified.is_none() || modified == shader.modified {
+                continue;
+            }
+            shader.modified = modified;
+
+            let id = ShaderId(index);
+            self.reload_errors.retain(|error| error.shader != id);
+
+            match options.compile(ShaderStage::Pixel, &shader.source, bindless_textures) {
+                Ok(bytecode) => {
+                    let previous = std::mem::replace(&mut shader.bytecode, bytecode);
+                    reloaded.push((id, previous));
+                }
+                Err(error) => self.reload_errors.push(ShaderReloadError {
+                    shader: id,
+                    path: path.clone(),
+                    message: error.message(),
+                }),
+            }
+        }
+
+        reloaded
+    }
+
+    // Goes back to a reloaded shader's previous bytecode, because the new
+    // bytecode turned out to be unusable, and records why.
+    pub(crate) fn reject_reload(&mut self, id: ShaderId, previous: ID3DBlob, message: String) {
+        let Some(shader) = self.shaders.get_mut(id.0).and_then(Option::as_mut) else {
+            return;
+        };
+        shader.bytecode = previous;
+
+        if let ShaderSource::HlslFile { path, .. } = &shader.source {
+            self.reload_errors.push(ShaderReloadError {
+                shader: id,
+                path: path.clone(),
+                message,
+            });
+        }
+    }
+
+    pub(crate) fn reload_errors(&self) -> &[ShaderReloadError] {
+        &self.reload_errors
+    }
+}
+
+impl ShaderSource {
+    fn modified(&self) -> Option<SystemTime> {
+        match self {
+            ShaderSource::HlslFile { path, .. } => std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            _ => None,
+        }
+    }
+}
+
+impl ShaderCompileOptions {
+    // Compiles one of the renderer's built-in shaders, or returns its
+    // precompiled bytecode.
+    pub(crate) fn compile_builtin(
+        &self,
+        stage: ShaderStage,
+        hlsl: &str,
+        bindless_textures: bool,
+    ) -> Result<ID3DBlob> {
+        if let ShaderCompiler::Precompiled(shaders) = &self.compiler {
+            return create_blob(match stage {
+                ShaderStage::Vertex => &shaders.vertex_shader,
+                ShaderStage::Pixel => &shaders.pixel_shader,
+            });
+        }
+
+        self.compile_hlsl(stage, hlsl, "main", bindless_textures)
+    }
+
+    pub(crate) fn compile(
+        &self,
+        stage: ShaderStage,
+        source: &ShaderSource,
+        bindless_textures: bool,
+    ) -> Result<ID3DBlob> {
+        match source {
+            ShaderSource::Hlsl {
+                source,
+                entry_point,
+            } => {
+                if let ShaderCompiler::Precompiled(_) = self.compiler {
+                    return Err(Error::new(
+                        E_INVALIDARG,
+                        "HLSL shaders can't be compiled with ShaderCompiler::Precompiled",
+                    ));
+                }
+                self.compile_hlsl(stage, source, entry_point, bindless_textures)
+            }
+            ShaderSource::HlslFile { path, entry_point } => {
+                let source = std::fs::read_to_string(path)?;
+                self.compile(
+                    stage,
+                    &ShaderSource::Hlsl {
+                        source,
+                        entry_point: entry_point.clone(),
+                    },
+                    bindless_textures,
+                )
+            }
+            ShaderSource::Bytecode(bytecode) => create_blob(bytecode),
+        }
+    }
+
+    fn compile_hlsl(
+        &self,
+        stage: ShaderStage,
+        hlsl: &str,
+        entry_point: &str,
+        bindless_textures: bool,
+    ) -> Result<ID3DBlob> {
+        let stage = match stage {
+            ShaderStage::Vertex => "vs",
+            ShaderStage::Pixel => "ps",
+        };
+
+        let dxc_target = |minimum: D3D_SHADER_MODEL| {
+            let model = self
+                .dxc_shader_model
+                .map_or(minimum, |model| D3D_SHADER_MODEL(model.0.max(minimum.0)));
+            format!("{stage}_{}_{}", model.0 >> 4, model.0 & 0xf)
+        };
+
+        let mut defines = self.defines.clone();
+        if bindless_textures {
+            defines.push((BINDLESS_TEXTURES_DEFINE.to_string(), "1".to_string()));
+        }
+
+        // Bindless textures need shader model 6.6, and a pipeline can't mix
+        // DXBC and DXIL shaders, so these are always compiled with DXC.
+        match (&self.compiler, bindless_textures) {
+            (_, true) => dxc::compile_shader(
+                hlsl,
+                entry_point,
+                &dxc_target(D3D_SHADER_MODEL_6_6),
+                &defines,
+                self,
+            ),
+            (ShaderCompiler::Dxc, false) => dxc::compile_shader(
+                hlsl,
+                entry_point,
+                &dxc_target(D3D_SHADER_MODEL_6_0),
+                &defines,
+                self,
+            ),
+            _ => fxc::compile_shader(hlsl, entry_point, &format!("{stage}_5_1"), &defines, self),
+        }
+    }
+}
+
+fn create_blob(data: &[u8]) -> Result<ID3DBlob> {
+    unsafe {
+        let blob = D3DCreateBlob(data.len())?;
+        std::ptr::copy_nonoverlapping(
+            data.as_ptr(),
+            blob.GetBufferPointer() as *mut u8,
+            data.len(),
+        );
+        Ok(blob)
+    }
+}
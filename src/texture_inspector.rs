@@ -0,0 +1,856 @@
+//
+// An ImGui window for browsing the textures the renderer knows about, with a
+// zoomable view of the selected texture and a readout of the hovered pixel.
+//
+
+use imgui::{Condition, MouseButton, TextureId, Ui};
+use windows::{
+    core::{Error, Interface, Result},
+    Win32::Graphics::{
+        Direct3D12::{
+            ID3D12Device, ID3D12GraphicsCommandList, ID3D12Resource, D3D12_BOX,
+            D3D12_HEAP_FLAG_NONE, D3D12_HEAP_PROPERTIES, D3D12_HEAP_TYPE_READBACK,
+            D3D12_PLACED_SUBRESOURCE_FOOTPRINT, D3D12_RANGE, D3D12_RESOURCE_BARRIER,
+            D3D12_RESOURCE_BARRIER_0, D3D12_RESOURCE_BARRIER_TYPE_TRANSITION, D3D12_RESOURCE_DESC,
+            D3D12_RESOURCE_DIMENSION_BUFFER, D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL,
+            D3D12_RESOURCE_STATES, D3D12_RESOURCE_STATE_COPY_DEST,
+            D3D12_RESOURCE_STATE_COPY_SOURCE, D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            D3D12_RESOURCE_TRANSITION_BARRIER, D3D12_SUBRESOURCE_FOOTPRINT,
+            D3D12_TEXTURE_COPY_LOCATION, D3D12_TEXTURE_COPY_LOCATION_0,
+            D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT, D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+            D3D12_TEXTURE_DATA_PITCH_ALIGNMENT, D3D12_TEXTURE_DATA_PLACEMENT_ALIGNMENT,
+            D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        },
+        Dxgi::Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+    },
+};
+
+use crate::{
+    formats::{
+        decode_texel, format_memory_size, format_name, is_block_compressed, readable_format,
+    },
+    ComponentMapping, ComponentSource, FrameFence, Renderer, ShaderId, ShaderSource,
+    TextureViewDesc,
+};
+
+// A texture listed by the inspector.
+pub(crate) struct InspectableTexture {
+    pub(crate) name: String,
+    pub(crate) resource: ID3D12Resource,
+    pub(crate) texture_id: TextureId,
+}
+
+pub(crate) struct TextureInspector {
+    // The selected texture's resource, only used to identify it
+    selected: Option<usize>,
+    mip: u32,
+    slice: u32,
+    channels: [bool; 4],
+    point_sampling: bool,
+    pixel_grid: bool,
+    zoom: f32,
+    pan: [f32; 2],
+    view: Option<InspectorView>,
+    // Pixel shaders for point sampling textures and texture arrays
+    point_shaders: [Option<ShaderId>; 2],
+    // Why the current view couldn't be drawn with a point sampling shader
+    point_shader_error: Option<Error>,
+    readback: PixelReadback,
+    // Why the most recent readback failed, if it did
+    readback_error: Option<Error>,
+}
+
+// How the selected texture is currently drawn.
+struct InspectorView {
+    resource: usize,
+    desc: TextureViewDesc,
+    point_sampling: bool,
+    texture_id: TextureId,
+    // False if no texture view could be registered, in which case the
+    // texture's own id is used
+    registered: bool,
+}
+
+impl Default for TextureInspector {
+    fn default() -> Self {
+        TextureInspector {
+            selected: None,
+            mip: 0,
+            slice: 0,
+            channels: [true; 4],
+            point_sampling: false,
+            pixel_grid: true,
+            zoom: 1.0,
+            pan: [0.0, 0.0],
+            view: None,
+            point_shaders: [None, None],
+            point_shader_error: None,
+            readback: PixelReadback::default(),
+            readback_error: None,
+        }
+    }
+}
+
+// The pixel grid is only drawn once texels are at least this many pixels wide
+const PIXEL_GRID_MIN_ZOOM: f32 = 8.0;
+
+impl TextureInspector {
+    // Returns the inspector's own texture view, so that it can be unregistered.
+    pub(crate) fn take_view(&mut self) -> Option<TextureId> {
+        self.view
+            .take()
+            .filter(|view| view.registered)
+            .map(|view| view.texture_id)
+    }
+
+    // Forgets everything that refers to the device.
+    pub(crate) fn device_changed(&mut self) {
+        self.view = None;
+        self.readback = PixelReadback::default();
+        self.readback_error = None;
+    }
+
+    // Releases the view and the readback buffer while the window is hidden.
+    // Copies in flight may still be writing to the buffer, so it's retired
+    // rather than dropped.
+    pub(crate) fn hide(&mut self, renderer: &mut Renderer) {
+        self.release_view(renderer);
+
+        let readback = std::mem::take(&mut self.readback);
+        if readback.buffer.is_some() {
+            renderer.deletion_queue.retire(readback);
+        }
+    }
+
+    pub(crate) fn readback_error(&self) -> Option<&Error> {
+        self.readback_error.as_ref()
+    }
+
+    pub(crate) fn view_texture_id(&self) -> Option<TextureId> {
+        self.view
+            .as_ref()
+            .filter(|view| view.registered)
+            .map(|view| view.texture_id)
+    }
+
+    pub(crate) fn show(&mut self, renderer: &mut Renderer, ui: &Ui, opened: &mut bool) {
+        let textures = renderer.inspectable_textures(self.view_texture_id());
+
+        if !textures
+            .iter()
+            .any(|texture| Some(resource_key(&texture.resource)) == self.selected)
+        {
+            self.selected = None;
+        }
+        self.readback.request = None;
+
+        ui.window("Textures")
+            .opened(opened)
+            .size([800.0, 600.0], Condition::FirstUseEver)
+            .build(|| {
+                ui.child_window("texture list")
+                    .size([250.0, 0.0])
+                    .border(true)
+                    .build(|| {
+                        for texture in &textures {
+                            let key = resource_key(&texture.resource);
+                            let desc = unsafe { texture.resource.GetDesc() };
+                            let label = format!(
+                                "{} ({}x{})##{:x}",
+                                texture.name, desc.Width, desc.Height, key
+                            );
+                            if ui
+                                .selectable_config(label)
+                                .selected(self.selected == Some(key))
+                                .build()
+                            {
+                                self.select(key);
+                            }
+                        }
+                    });
+
+                ui.same_line();
+
+                ui.child_window("texture").build(|| {
+                    match textures
+                        .iter()
+                        .find(|texture| Some(resource_key(&texture.resource)) == self.selected)
+                    {
+                        Some(texture) => self.show_texture(renderer, ui, texture),
+                        None => ui.text_disabled("Select a texture"),
+                    }
+                });
+            });
+
+        if !*opened {
+            self.hide(renderer);
+        } else if self.selected.is_none() {
+            self.release_view(renderer);
+        }
+    }
+
+    fn select(&mut self, key: usize) {
+        if self.selected != Some(key) {
+            self.selected = Some(key);
+            self.mip = 0;
+            self.slice = 0;
+            self.zoom = 1.0;
+            self.pan = [0.0, 0.0];
+        }
+    }
+
+    fn show_texture(&mut self, renderer: &mut Renderer, ui: &Ui, texture: &InspectableTexture) {
+        let desc = unsafe { texture.resource.GetDesc() };
+        let memory = unsafe { renderer.device().GetResourceAllocationInfo(0, &[desc]) };
+        let array_size = desc.DepthOrArraySize as u32;
+
+        ui.text(format!(
+            "{}x{}, {} mips{}",
+            desc.Width,
+            desc.Height,
+            desc.MipLevels,
+            if array_size > 1 {
+                format!(", {array_size} slices")
+            } else {
+                String::new()
+            }
+        ));
+        ui.text(format!(
+            "{}, {}",
+            format_name(desc.Format),
+            format_memory_size(memory.SizeInBytes)
+        ));
+
+        // Controls
+        self.mip = self.mip.min(desc.MipLevels as u32 - 1);
+        self.slice = self.slice.min(array_size - 1);
+        if desc.MipLevels > 1 {
+            ui.slider("Mip", 0, desc.MipLevels as u32 - 1, &mut self.mip);
+        }
+        if array_size > 1 {
+            ui.slider("Slice", 0, array_size - 1, &mut self.slice);
+        }
+
+        for (index, name) in ["R", "G", "B", "A"].iter().enumerate() {
+            ui.checkbox(name, &mut self.channels[index]);
+            ui.same_line();
+        }
+        ui.checkbox("Point sampling", &mut self.point_sampling);
+        ui.same_line();
+        ui.checkbox("Pixel grid", &mut self.pixel_grid);
+        ui.same_line();
+        if ui.button("1:1") {
+            self.zoom = 1.0;
+            self.pan = [0.0, 0.0];
+        }
+        ui.same_line();
+        let fit = ui.button("Fit");
+
+        let texture_id = self.update_view(renderer, texture, array_size > 1);
+        if !self.view.as_ref().is_some_and(|view| view.registered) {
+            ui.text_disabled(
+                "Channels, mips, slices and sampling need a descriptor from set_texture_view_descriptors",
+            );
+        } else if let Some(error) = self.point_shader_error.as_ref() {
+            ui.text_disabled(format!("Point sampling isn't available: {error}"));
+        }
+
+        // The canvas fills the rest of the window, above the pixel readout
+        let image_size = [
+            (desc.Width >> self.mip).max(1) as f32,
+            (desc.Height >> self.mip).max(1) as f32,
+        ];
+        let canvas_min = ui.cursor_screen_pos();
+        let available = ui.content_region_avail();
+        let canvas_size = [
+            available[0].max(1.0),
+            (available[1] - ui.text_line_height_with_spacing()).max(1.0),
+        ];
+        let canvas_max = [
+            canvas_min[0] + canvas_size[0],
+            canvas_min[1] + canvas_size[1],
+        ];
+
+        if fit {
+            self.zoom = (canvas_size[0] / image_size[0]).min(canvas_size[1] / image_size[1]);
+            self.pan = [0.0, 0.0];
+        }
+
+        ui.invisible_button("canvas", canvas_size);
+        let hovered = ui.is_item_hovered();
+        let io = ui.io();
+
+        // The image is centered in the canvas, then offset by the pan
+        let origin = |zoom: f32, pan: [f32; 2]| {
+            [
+                canvas_min[0] + (canvas_size[0] - image_size[0] * zoom) / 2.0 + pan[0],
+                canvas_min[1] + (canvas_size[1] - image_size[1] * zoom) / 2.0 + pan[1],
+            ]
+        };
+
+        if hovered && io.mouse_wheel != 0.0 {
+            // Zoom around the cursor, keeping the texel under it in place
+            let old_origin = origin(self.zoom, self.pan);
+            let zoom = (self.zoom * 1.25f32.powf(io.mouse_wheel)).clamp(1.0 / 64.0, 256.0);
+            let ratio = zoom / self.zoom;
+            let zoomed_origin = origin(zoom, self.pan);
+            for (axis, pan) in self.pan.iter_mut().enumerate() {
+                let new_origin =
+                    io.mouse_pos[axis] - (io.mouse_pos[axis] - old_origin[axis]) * ratio;
+                *pan += new_origin - zoomed_origin[axis];
+            }
+            self.zoom = zoom;
+        }
+
+        if ui.is_item_active() && ui.is_mouse_dragging(MouseButton::Left) {
+            self.pan[0] += io.mouse_delta[0];
+            self.pan[1] += io.mouse_delta[1];
+        }
+
+        let image_min = origin(self.zoom, self.pan);
+        let image_max = [
+            image_min[0] + image_size[0] * self.zoom,
+            image_min[1] + image_size[1] * self.zoom,
+        ];
+
+        let draw_list = ui.get_window_draw_list();
+        draw_list.with_clip_rect_intersect(canvas_min, canvas_max, || {
+            draw_list
+                .add_rect(canvas_min, canvas_max, [0.1, 0.1, 0.1, 1.0])
+                .filled(true)
+                .build();
+            draw_list
+                .add_image(texture_id, image_min, image_max)
+                .build();
+
+            if self.pixel_grid && self.zoom >= PIXEL_GRID_MIN_ZOOM {
+                let color = [0.5, 0.5, 0.5, 0.5];
+                let visible = |axis: usize| {
+                    let first = ((canvas_min[axis] - image_min[axis]) / self.zoom).floor();
+                    let last = ((canvas_max[axis] - image_min[axis]) / self.zoom).ceil();
+                    (first.max(0.0) as u32)..=(last.min(image_size[axis]) as u32)
+                };
+
+                let top = image_min[1].max(canvas_min[1]);
+                let bottom = image_max[1].min(canvas_max[1]);
+                for x in visible(0) {
+                    let x = image_min[0] + x as f32 * self.zoom;
+                    draw_list.add_line([x, top], [x, bottom], color).build();
+                }
+
+                let left = image_min[0].max(canvas_min[0]);
+                let right = image_max[0].min(canvas_max[0]);
+                for y in visible(1) {
+                    let y = image_min[1] + y as f32 * self.zoom;
+                    draw_list.add_line([left, y], [right, y], color).build();
+                }
+            }
+        });
+
+        // Hovered pixel readout
+        let texel = [
+            ((io.mouse_pos[0] - image_min[0]) / self.zoom).floor(),
+            ((io.mouse_pos[1] - image_min[1]) / self.zoom).floor(),
+        ];
+        if hovered
+            && (0.0..image_size[0]).contains(&texel[0])
+            && (0.0..image_size[1]).contains(&texel[1])
+        {
+            let x = texel[0] as u32;
+            let y = texel[1] as u32;
+
+            // Depth stencil resources can only be copied as a whole, and block
+            // compressed ones a block at a time
+            if desc.Flags.contains(D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL)
+                || is_block_compressed(desc.Format)
+            {
+                ui.text(format!("{x}, {y}"));
+            } else {
+                let request = ReadbackRequest {
+                    resource: texture.resource.clone(),
+                    subresource: self.mip + self.slice * desc.MipLevels as u32,
+                    x,
+                    y,
+                    format: desc.Format,
+                };
+
+                let value = match self.readback_error.as_ref() {
+                    Some(error) => format!("readback failed: {error}"),
+                    None => self
+                        .readback
+                        .value
+                        .as_ref()
+                        .filter(|value| value.matches(&request))
+                        .map_or("...".to_string(), |value| value.text.clone()),
+                };
+                ui.text(format!("{x}, {y}: {value}"));
+
+                self.readback.request = Some(request);
+            }
+        } else {
+            ui.text("");
+        }
+    }
+
+    // Returns the texture id to draw the selected texture with, registering a
+    // new texture view if the settings have changed.
+    fn update_view(
+        &mut self,
+        renderer: &mut Renderer,
+        texture: &InspectableTexture,
+        is_array: bool,
+    ) -> TextureId {
+        let resource = resource_key(&texture.resource);
+        let desc = TextureViewDesc {
+            format: readable_format(unsafe { texture.resource.GetDesc() }.Format),
+            most_detailed_mip: self.mip,
+            mip_levels: 1,
+            array_slice: self.slice,
+            component_mapping: self.component_mapping(),
+        };
+
+        if let Some(view) = self.view.as_ref() {
+            if view.resource == resource
+                && view.desc == desc
+                && view.point_sampling == self.point_sampling
+            {
+                return view.texture_id;
+            }
+        }
+
+        self.release_view(renderer);
+        self.point_shader_error = None;
+
+        let view = match renderer.register_texture_view(&texture.resource, &desc) {
+            Ok(texture_id) => {
+                // Without the shader the view is still drawn, filtered
+                if self.point_sampling {
+                    match self.point_shader(renderer, is_array) {
+                        Ok(shader) => renderer.set_texture_pixel_shader(texture_id, Some(shader)),
+                        Err(error) => self.point_shader_error = Some(error),
+                    }
+                }
+
+                InspectorView {
+                    resource,
+                    desc,
+                    point_sampling: self.point_sampling,
+                    texture_id,
+                    registered: true,
+                }
+            }
+            Err(_) => InspectorView {
+                resource,
+                desc,
+                point_sampling: self.point_sampling,
+                texture_id: texture.texture_id,
+                registered: false,
+            },
+        };
+
+        let texture_id = view.texture_id;
+        self.view = Some(view);
+        texture_id
+    }
+
+    fn release_view(&mut self, renderer: &mut Renderer) {
+        if let Some(texture_id) = self.take_view() {
+            renderer.unregister_texture_view(texture_id);
+        }
+    }
+
+    fn component_mapping(&self) -> ComponentMapping {
+        let [r, g, b, a] = self.channels;
+        let sources = [
+            ComponentSource::Red,
+            ComponentSource::Green,
+            ComponentSource::Blue,
+            ComponentSource::Alpha,
+        ];
+
+        // A single channel is shown as grayscale
+        let enabled: Vec<ComponentSource> = sources
+            .iter()
+            .zip(self.channels)
+            .filter_map(|(source, enabled)| enabled.then_some(*source))
+            .collect();
+        if let [source] = enabled[..] {
+            return ComponentMapping::grayscale(source);
+        }
+
+        ComponentMapping {
+            a: if a {
+                ComponentSource::Alpha
+            } else {
+                ComponentSource::One
+            },
+            ..ComponentMapping::channels(r, g, b)
+        }
+    }
+
+    fn point_shader(&mut self, renderer: &mut Renderer, is_array: bool) -> Result<ShaderId> {
+        let slot = &mut self.point_shaders[is_array as usize];
+        if let Some(shader) = *slot {
+            return Ok(shader);
+        }
+
+        let shader = renderer.register_pixel_shader(ShaderSource::Hlsl {
+            source: point_sampling_pixel_shader(is_array),
+            entry_point: "main".to_string(),
+        })?;
+        *slot = Some(shader);
+        Ok(shader)
+    }
+
+    // Copies the hovered pixel into a readback buffer, and picks up the
+    // results of earlier copies that the GPU has finished.  The hovered pixel
+    // isn't worth losing the frame over, so a failure is kept for the window
+    // to show rather than returned.
+    pub(crate) fn record_readback(
+        &mut self,
+        device: &ID3D12Device,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+        frame_fence: &FrameFence,
+        frame_index: usize,
+        num_frames_in_flight: usize,
+    ) {
+        self.readback_error = self
+            .try_record_readback(
+                device,
+                graphics_command_list,
+                frame_fence,
+                frame_index,
+                num_frames_in_flight,
+            )
+            .err();
+    }
+
+    fn try_record_readback(
+        &mut self,
+        device: &ID3D12Device,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+        frame_fence: &FrameFence,
+        frame_index: usize,
+        num_frames_in_flight: usize,
+    ) -> Result<()> {
+        self.readback
+            .collect(frame_fence, frame_index, num_frames_in_flight)?;
+
+        if let Some(request) = self.readback.request.take() {
+            self.readback.record(
+                device,
+                graphics_command_list,
+                request,
+                frame_index,
+                frame_fence.next_value,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn resource_key(resource: &ID3D12Resource) -> usize {
+    resource.as_raw() as usize
+}
+
+// Draws a texture without filtering, by loading the texel under each pixel.
+// As with the array slice shader, IMGUI_BINDLESS selects how the texture is
+// found.
+fn point_sampling_pixel_shader(is_array: bool) -> String {
+    let (texture_type, load) = if is_array {
+        (
+            "Texture2DArray",
+            "uint width, height, elements;
+        texture0.GetDimensions(width, height, elements);
+        float4 texel = texture0.Load(int4(input.uv * float2(width, height), 0, 0));",
+        )
+    } else {
+        (
+            "Texture2D",
+            "uint width, height;
+        texture0.GetDimensions(width, height);
+        float4 texel = texture0.Load(int3(input.uv * float2(width, height), 0));",
+        )
+    };
+
+    format!(
+        r"
+    struct PS_INPUT {{
+        float4 pos: SV_POSITION;
+        float4 col: COLOR0;
+        float2 uv: TEXCOORD0;
+    }};
+
+    #ifdef IMGUI_BINDLESS
+    cbuffer textureConstants: register(b1) {{
+        uint textureIndex;
+    }};
+    #else
+    {texture_type} texture0: register(t0);
+    #endif
+
+    float4 main(PS_INPUT input): SV_Target {{
+    #ifdef IMGUI_BINDLESS
+        {texture_type} texture0 = ResourceDescriptorHeap[textureIndex];
+    #endif
+        {load}
+        return input.col * texel;
+    }}
+    "
+    )
+}
+
+// Reads single pixels back from the GPU.  Each copy goes into its own slot of
+// the readback buffer, which is read once the frame that made it has
+// finished.
+const READBACK_SLOTS: usize = 4;
+
+#[derive(Default)]
+struct PixelReadback {
+    request: Option<ReadbackRequest>,
+    buffer: Option<ID3D12Resource>,
+    slots: [Option<PendingReadback>; READBACK_SLOTS],
+    value: Option<PixelValue>,
+}
+
+struct ReadbackRequest {
+    resource: ID3D12Resource,
+    subresource: u32,
+    x: u32,
+    y: u32,
+    format: DXGI_FORMAT,
+}
+
+struct PendingReadback {
+    request: ReadbackRequest, // keeps the resource alive until the copy is done
+    last_used_frame: usize,
+    fence_value: u64,
+}
+
+struct PixelValue {
+    resource: usize,
+    subresource: u32,
+    x: u32,
+    y: u32,
+    text: String,
+}
+
+impl PixelValue {
+    fn matches(&self, request: &ReadbackRequest) -> bool {
+        self.resource == resource_key(&request.resource)
+            && self.subresource == request.subresource
+            && self.x == request.x
+            && self.y == request.y
+    }
+}
+
+impl PixelReadback {
+    fn collect(
+        &mut self,
+        frame_fence: &FrameFence,
+        frame_index: usize,
+        num_frames_in_flight: usize,
+    ) -> Result<()> {
+        let Some(buffer) = self.buffer.as_ref() else {
+            return Ok(());
+        };
+
+        // Only the most recent finished copy is interesting
+        let completed: Vec<usize> = (0..READBACK_SLOTS)
+            .filter(|slot| {
+                self.slots[*slot].as_ref().is_some_and(|pending| {
+                    frame_fence.has_completed(
+                        frame_index,
+                        num_frames_in_flight,
+                        pending.last_used_frame,
+                        pending.fence_value,
+                    )
+                })
+            })
+            .collect();
+        let Some(slot) = completed.iter().copied().min_by_key(|slot| {
+            let last_used_frame = self.slots[*slot].as_ref().unwrap().last_used_frame;
+            frame_index.wrapping_sub(last_used_frame)
+        }) else {
+            return Ok(());
+        };
+        for other in completed {
+            if other != slot {
+                self.slots[other] = None;
+            }
+        }
+        let pending = self.slots[slot].take().unwrap();
+
+        let offset = slot * D3D12_TEXTURE_DATA_PLACEMENT_ALIGNMENT as usize;
+        let mut data = [0u8; 16];
+        unsafe {
+            let mut mapped = std::ptr::null_mut();
+            buffer.Map(
+                0,
+                Some(&D3D12_RANGE {
+                    Begin: offset,
+                    End: offset + data.len(),
+                }),
+                Some(&mut mapped),
+            )?;
+            std::ptr::copy_nonoverlapping(
+                mapped.cast::<u8>().add(offset),
+                data.as_mut_ptr(),
+                data.len(),
+            );
+            buffer.Unmap(0, Some(&D3D12_RANGE::default()));
+        }
+
+        let request = pending.request;
+        self.value = Some(PixelValue {
+            resource: resource_key(&request.resource),
+            subresource: request.subresource,
+            x: request.x,
+            y: request.y,
+            text: decode_texel(request.format, &data)
+                .unwrap_or_else(|| format!("{} not supported", format_name(request.format))),
+        });
+
+        Ok(())
+    }
+
+    // The texture is expected to be in the pixel shader resource state, as it
+    // is whenever it's drawn.  If every slot is in use the request is
+    // dropped, and made again next frame.
+    fn record(
+        &mut self,
+        device: &ID3D12Device,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+        request: ReadbackRequest,
+        frame_index: usize,
+        fence_value: u64,
+    ) -> Result<()> {
+        let Some(slot) = self.slots.iter().position(Option::is_none) else {
+            return Ok(());
+        };
+
+        if self.buffer.is_none() {
+            self.buffer = Some(create_readback_buffer(device)?);
+        }
+        let buffer = self.buffer.as_ref().unwrap();
+
+        let src_location = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: unsafe { std::mem::transmute_copy(&request.resource) },
+            Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+                SubresourceIndex: request.subresource,
+            },
+        };
+
+        let dst_location = D3D12_TEXTURE_COPY_LOCATION {
+            pResource: unsafe { std::mem::transmute_copy(buffer) },
+            Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+            Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+                PlacedFootprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT {
+                    Offset: (slot as u32 * D3D12_TEXTURE_DATA_PLACEMENT_ALIGNMENT) as u64,
+                    Footprint: D3D12_SUBRESOURCE_FOOTPRINT {
+                        Format: request.format,
+                        Width: 1,
+                        Height: 1,
+                        Depth: 1,
+                        RowPitch: D3D12_TEXTURE_DATA_PITCH_ALIGNMENT,
+                    },
+                },
+            },
+        };
+
+        unsafe {
+            graphics_command_list.ResourceBarrier(&[transition_barrier(
+                &request.resource,
+                request.subresource,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+            )]);
+            graphics_command_list.CopyTextureRegion(
+                &dst_location,
+                0,
+                0,
+                0,
+                &src_location,
+                Some(&D3D12_BOX {
+                    left: request.x,
+                    top: request.y,
+                    front: 0,
+                    right: request.x + 1,
+                    bottom: request.y + 1,
+                    back: 1,
+                }),
+            );
+            graphics_command_list.ResourceBarrier(&[transition_barrier(
+                &request.resource,
+                request.subresource,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+                D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            )]);
+        }
+
+        self.slots[slot] = Some(PendingReadback {
+            request,
+            last_used_frame: frame_index,
+            fence_value,
+        });
+
+        Ok(())
+    }
+}
+
+fn create_readback_buffer(device: &ID3D12Device) -> Result<ID3D12Resource> {
+    let desc = D3D12_RESOURCE_DESC {
+        Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+        Width: (READBACK_SLOTS as u32 * D3D12_TEXTURE_DATA_PLACEMENT_ALIGNMENT) as u64,
+        Height: 1,
+        DepthOrArraySize: 1,
+        MipLevels: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        ..Default::default()
+    };
+
+    let mut buffer: Option<ID3D12Resource> = None;
+    unsafe {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_READBACK,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &desc,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            None,
+            &mut buffer,
+        )?;
+    }
+    let buffer = buffer.unwrap();
+    unsafe { buffer.SetName(windows::core::h!("imgui texture inspector readback")) }?;
+
+    Ok(buffer)
+}
+
+fn transition_barrier(
+    resource: &ID3D12Resource,
+    subresource: u32,
+    state_before: D3D12_RESOURCE_STATES,
+    state_after: D3D12_RESOURCE_STATES,
+) -> D3D12_RESOURCE_BARRIER {
+    D3D12_RESOURCE_BARRIER {
+        Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+        Anonymous: D3D12_RESOURCE_BARRIER_0 {
+            Transition: std::mem::ManuallyDrop::new(D3D12_RESOURCE_TRANSITION_BARRIER {
+                pResource: unsafe { std::mem::transmute_copy(resource) },
+                Subresource: subresource,
+                StateBefore: state_before,
+                StateAfter: state_after,
+            }),
+        },
+        ..Default::default()
+    }
+}
@@ -0,0 +1,108 @@
+//
+// Optional validation of the texture ids used by draw commands, so that a stale
+// or garbage id is reported rather than becoming a GPU page fault.
+//
+
+use std::collections::HashSet;
+
+use imgui::TextureId;
+use windows::Win32::Graphics::Direct3D12::{
+    D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_GPU_DESCRIPTOR_HANDLE,
+};
+
+/// A draw command that used a texture id that wasn't registered with the
+/// renderer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidTextureId {
+    /// The index of the draw list in the frame's draw data.
+    pub draw_list: usize,
+
+    /// The index of the command in the draw list.
+    pub command: usize,
+
+    pub texture_id: TextureId,
+}
+
+#[derive(Default)]
+pub(crate) struct TextureValidation {
+    pub(crate) placeholder: Option<PlaceholderDescriptor>,
+    texture_ids: HashSet<TextureId>,
+    descriptor_ranges: Vec<DescriptorRange>,
+    pub(crate) invalid_texture_ids: Vec<InvalidTextureId>,
+}
+
+// Where the SRV for the texture drawn in place of invalid texture ids goes, and
+// the texture id that refers to it.
+#[derive(Clone, Copy)]
+pub(crate) struct PlaceholderDescriptor {
+    pub(crate) cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    pub(crate) gpu_desc_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
+    pub(crate) texture_id: TextureId,
+}
+
+struct DescriptorRange {
+    start: u64,
+    count: u64,
+    increment: u64,
+}
+
+impl TextureValidation {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.placeholder.is_some()
+    }
+
+    pub(crate) fn register_texture_id(&mut self, texture_id: TextureId) {
+        self.texture_ids.insert(texture_id);
+    }
+
+    pub(crate) fn unregister_texture_id(&mut self, texture_id: TextureId) {
+        self.texture_ids.remove(&texture_id);
+    }
+
+    // Registers the texture ids start, start + increment, ... for count ids.
+    pub(crate) fn register_id_range(&mut self, start: u64, count: u32, increment: u64) {
+        self.descriptor_ranges.push(DescriptorRange {
+            start,
+            count: count as u64,
+            increment,
+        });
+    }
+
+    pub(crate) fn is_valid(&self, texture_id: TextureId) -> bool {
+        if self.texture_ids.contains(&texture_id) {
+            return true;
+        }
+
+        let ptr = texture_id.id() as u64;
+        self.descriptor_ranges.iter().any(|range| {
+            ptr >= range.start
+                && (ptr - range.start) % range.increment == 0
+                && (ptr - range.start) / range.increment < range.count
+        })
+    }
+
+    // Returns the texture id to draw a command with, substituting the
+    // placeholder and recording an error if the texture id isn't valid.
+    pub(crate) fn validate(
+        &mut self,
+        texture_id: TextureId,
+        draw_list: usize,
+        command: usize,
+    ) -> TextureId {
+        let placeholder = self
+            .placeholder
+            .expect("texture validation must be enabled");
+
+        if self.is_valid(texture_id) {
+            return texture_id;
+        }
+
+        self.invalid_texture_ids.push(InvalidTextureId {
+            draw_list,
+            command,
+            texture_id,
+        });
+
+        placeholder.texture_id
+    }
+}
@@ -0,0 +1,392 @@
+//
+// Shader resource views that the renderer creates for the app's textures, in a
+// range of descriptors that the app sets aside for it.
+//
+
+use imgui::TextureId;
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::{E_INVALIDARG, E_OUTOFMEMORY},
+        Graphics::{
+            Direct3D12::{
+                ID3D12Device, ID3D12Resource, D3D12_CPU_DESCRIPTOR_HANDLE,
+                D3D12_GPU_DESCRIPTOR_HANDLE, D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                D3D12_SHADER_COMPONENT_MAPPING,
+                D3D12_SHADER_COMPONENT_MAPPING_ALWAYS_SET_BIT_AVOIDING_ZEROMEM_MISTAKES,
+                D3D12_SHADER_COMPONENT_MAPPING_FORCE_VALUE_0,
+                D3D12_SHADER_COMPONENT_MAPPING_FORCE_VALUE_1,
+                D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_0,
+                D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_1,
+                D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_2,
+                D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_3,
+                D3D12_SHADER_COMPONENT_MAPPING_SHIFT, D3D12_SHADER_RESOURCE_VIEW_DESC,
+                D3D12_SHADER_RESOURCE_VIEW_DESC_0, D3D12_SRV_DIMENSION_TEXTURE2D,
+                D3D12_SRV_DIMENSION_TEXTURE2DARRAY, D3D12_TEX2D_ARRAY_SRV, D3D12_TEX2D_SRV,
+            },
+            Dxgi::Common::{DXGI_FORMAT, DXGI_FORMAT_UNKNOWN},
+        },
+    },
+};
+
+use crate::ShaderId;
+
+/// Describes how a texture registered with
+/// [`crate::Renderer::register_texture_view`] is read when it's drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureViewDesc {
+    /// The format to read the texture as, or `DXGI_FORMAT_UNKNOWN` to use the
+    /// resource's format.  Typeless and depth resources need a readable
+    /// format, such as `DXGI_FORMAT_R32_FLOAT` for a `DXGI_FORMAT_R32_TYPELESS`
+    /// depth buffer.
+    pub format: DXGI_FORMAT,
+
+    pub most_detailed_mip: u32,
+
+    /// The number of mips to view, or `u32::MAX` for all of them from
+    /// `most_detailed_mip` down.
+    pub mip_levels: u32,
+
+    /// The slice of a texture array to view.  Ignored for textures that
+    /// aren't arrays.
+    pub array_slice: u32,
+
+    pub component_mapping: ComponentMapping,
+}
+
+impl Default for TextureViewDesc {
+    fn default() -> Self {
+        TextureViewDesc {
+            format: DXGI_FORMAT_UNKNOWN,
+            most_detailed_mip: 0,
+            mip_levels: u32::MAX,
+            array_slice: 0,
+            component_mapping: ComponentMapping::IDENTITY,
+        }
+    }
+}
+
+/// Where each channel seen by the pixel shader comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComponentMapping {
+    pub r: ComponentSource,
+    pub g: ComponentSource,
+    pub b: ComponentSource,
+    pub a: ComponentSource,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentSource {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Zero,
+    One,
+}
+
+impl ComponentMapping {
+    pub const IDENTITY: ComponentMapping = ComponentMapping {
+        r: ComponentSource::Red,
+        g: ComponentSource::Green,
+        b: ComponentSource::Blue,
+        a: ComponentSource::Alpha,
+    };
+
+    /// Shows a single channel as opaque grayscale, for example the alpha
+    /// channel with `ComponentSource::Alpha`.
+    pub const fn grayscale(source: ComponentSource) -> Self {
+        ComponentMapping {
+            r: source,
+            g: source,
+            b: source,
+            a: ComponentSource::One,
+        }
+    }
+
+    /// Shows only the given channels, as an opaque image.
+    pub const fn channels(r: bool, g: bool, b: bool) -> Self {
+        const fn pick(enabled: bool, source: ComponentSource) -> ComponentSource {
+            if enabled {
+                source
+            } else {
+                ComponentSource::Zero
+            }
+        }
+
+        ComponentMapping {
+            r: pick(r, ComponentSource::Red),
+            g: pick(g, ComponentSource::Green),
+            b: pick(b, ComponentSource::Blue),
+            a: ComponentSource::One,
+        }
+    }
+
+    // The equivalent of D3D12_ENCODE_SHADER_4_COMPONENT_MAPPING
+    fn encode(&self) -> u32 {
+        [self.r, self.g, self.b, self.a].iter().enumerate().fold(
+            D3D12_SHADER_COMPONENT_MAPPING_ALWAYS_SET_BIT_AVOIDING_ZEROMEM_MISTAKES,
+            |mapping, (index, source)| {
+                mapping
+                    | (source.mapping().0 as u32)
+                        << (index as u32 * D3D12_SHADER_COMPONENT_MAPPING_SHIFT)
+            },
+        )
+    }
+}
+
+impl ComponentSource {
+    fn mapping(&self) -> D3D12_SHADER_COMPONENT_MAPPING {
+        match self {
+            ComponentSource::Red => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_0,
+            ComponentSource::Green => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_1,
+            ComponentSource::Blue => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_2,
+            ComponentSource::Alpha => D3D12_SHADER_COMPONENT_MAPPING_FROM_MEMORY_COMPONENT_3,
+            ComponentSource::Zero => D3D12_SHADER_COMPONENT_MAPPING_FORCE_VALUE_0,
+            ComponentSource::One => D3D12_SHADER_COMPONENT_MAPPING_FORCE_VALUE_1,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct TextureViews {
+    descriptors: Option<DescriptorRange>,
+    next_unused: u32,
+    free: Vec<u32>,
+    pub(crate) views: Vec<TextureView>,
+    pub(crate) retired: Vec<RetiredView>,
+    // The pixel shader used to sample array slices, registered on first use
+    pub(crate) array_shader: Option<ShaderId>,
+}
+
+#[derive(Clone, Copy)]
+struct DescriptorRange {
+    cpu_start: D3D12_CPU_DESCRIPTOR_HANDLE,
+    gpu_start: D3D12_GPU_DESCRIPTOR_HANDLE,
+    count: u32,
+    descriptor_size: u64,
+}
+
+pub(crate) struct TextureView {
+    pub(crate) texture_id: TextureId,
+    pub(crate) resource: ID3D12Resource,
+    pub(crate) is_array: bool,
+    index: u32,
+}
+
+// The descriptor of a view that has been unregistered, but may still be used
+// by frames in flight.  The view's resource is kept alive by the deletion
+// queue.
+pub(crate) struct RetiredView {
+    index: u32,
+    pub(crate) last_used_frame: usize,
+    pub(crate) fence_value: u64,
+}
+
+impl TextureViews {
+    // Forgets every view, and the descriptors they were created in, returning
+    // the views' resources, which frames in flight may be using.
+    pub(crate) fn clear(&mut self) -> Vec<ID3D12Resource> {
+        let views = std::mem::replace(
+            self,
+            TextureViews {
+                array_shader: self.array_shader,
+                ..Default::default()
+            },
+        );
+
+        views.views.into_iter().map(|view| view.resource).collect()
+    }
+
+    pub(crate) fn set_descriptors(
+        &mut self,
+        cpu_start: D3D12_CPU_DESCRIPTOR_HANDLE,
+        gpu_start: D3D12_GPU_DESCRIPTOR_HANDLE,
+        count: u32,
+        descriptor_size: u64,
+    ) -> Vec<ID3D12Resource> {
+        let resources = self.clear();
+        self.descriptors = Some(DescriptorRange {
+            cpu_start,
+            gpu_start,
+            count,
+            descriptor_size,
+        });
+
+        resources
+    }
+
+    pub(crate) fn allocate(&mut self) -> Result<u32> {
+        if let Some(index) = self.free.pop() {
+            return Ok(index);
+        }
+
+        match self.descriptors {
+            Some(descriptors) if self.next_unused < descriptors.count => {
+                self.next_unused += 1;
+                Ok(self.next_unused - 1)
+            }
+            _ => Err(E_OUTOFMEMORY.into()),
+        }
+    }
+
+    pub(crate) fn cpu_handle(&self, index: u32) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        let descriptors = self.descriptors.unwrap();
+        D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: descriptors.cpu_start.ptr + (index as u64 * descriptors.descriptor_size) as usize,
+        }
+    }
+
+    pub(crate) fn gpu_handle(&self, index: u32) -> D3D12_GPU_DESCRIPTOR_HANDLE {
+        let descriptors = self.descriptors.unwrap();
+        D3D12_GPU_DESCRIPTOR_HANDLE {
+            ptr: descriptors.gpu_start.ptr + index as u64 * descriptors.descriptor_size,
+        }
+    }
+
+    pub(crate) fn free(&mut self, index: u32) {
+        self.free.push(index);
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        index: u32,
+        texture_id: TextureId,
+        resource: ID3D12Resource,
+        is_array: bool,
+    ) {
+        self.views.push(TextureView {
+            texture_id,
+            resource,
+            is_array,
+            index,
+        });
+    }
+
+    // The descriptor isn't reused until the frames that may have drawn it have
+    // finished, which release_retired checks for.  The caller keeps the
+    // returned view's resource alive until then.
+    pub(crate) fn remove(
+        &mut self,
+        texture_id: TextureId,
+        last_used_frame: usize,
+        fence_value: u64,
+    ) -> Option<TextureView> {
+        let position = self
+            .views
+            .iter()
+            .position(|view| view.texture_id == texture_id)?;
+        let view = self.views.swap_remove(position);
+
+        self.retired.push(RetiredView {
+            index: view.index,
+            last_used_frame,
+            fence_value,
+        });
+
+        Some(view)
+    }
+
+    pub(crate) fn release_retired(&mut self, is_complete: impl Fn(&RetiredView) -> bool) {
+        let (complete, retired) = std::mem::take(&mut self.retired)
+            .into_iter()
+            .partition::<Vec<_>, _>(is_complete);
+
+        self.free.extend(complete.iter().map(|view| view.index));
+        self.retired = retired;
+    }
+
+    pub(crate) fn view_gpu_handle(&self, view: &TextureView) -> D3D12_GPU_DESCRIPTOR_HANDLE {
+        self.gpu_handle(view.index)
+    }
+}
+
+// Returns whether the view needs the array slice pixel shader.
+pub(crate) fn create_texture_view_srv(
+    device: &ID3D12Device,
+    resource: &ID3D12Resource,
+    desc: &TextureViewDesc,
+    cpu_desc_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+) -> Result<bool> {
+    let resource_desc = unsafe { resource.GetDesc() };
+
+    // The pixel shaders only sample single sampled 2D textures
+    if resource_desc.Dimension != D3D12_RESOURCE_DIMENSION_TEXTURE2D
+        || resource_desc.SampleDesc.Count > 1
+    {
+        return Err(E_INVALIDARG.into());
+    }
+
+    let is_array = resource_desc.DepthOrArraySize > 1;
+    let (view_dimension, view) = if is_array {
+        (
+            D3D12_SRV_DIMENSION_TEXTURE2DARRAY,
+            D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                Texture2DArray: D3D12_TEX2D_ARRAY_SRV {
+                    MostDetailedMip: desc.most_detailed_mip,
+                    MipLevels: desc.mip_levels,
+                    FirstArraySlice: desc.array_slice,
+                    ArraySize: 1,
+                    ..Default::default()
+                },
+            },
+        )
+    } else {
+        (
+            D3D12_SRV_DIMENSION_TEXTURE2D,
+            D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                Texture2D: D3D12_TEX2D_SRV {
+                    MostDetailedMip: desc.most_detailed_mip,
+                    MipLevels: desc.mip_levels,
+                    ..Default::default()
+                },
+            },
+        )
+    };
+
+    unsafe {
+        device.CreateShaderResourceView(
+            resource,
+            Some(&D3D12_SHADER_RESOURCE_VIEW_DESC {
+                Format: if desc.format == DXGI_FORMAT_UNKNOWN {
+                    resource_desc.Format
+                } else {
+                    desc.format
+                },
+                ViewDimension: view_dimension,
+                Shader4ComponentMapping: desc.component_mapping.encode(),
+                Anonymous: view,
+            }),
+            cpu_desc_handle,
+        );
+    }
+
+    Ok(is_array)
+}
+
+// Samples the single slice in a texture array view.  IMGUI_BINDLESS, defined
+// by the shader compiler, selects how the texture is found.
+pub(crate) const ARRAY_SLICE_PIXEL_SHADER: &str = r"
+    struct PS_INPUT {
+        float4 pos: SV_POSITION;
+        float4 col: COLOR0;
+        float2 uv: TEXCOORD0;
+    };
+
+    SamplerState sampler0: register(s0);
+
+    #ifdef IMGUI_BINDLESS
+    cbuffer textureConstants: register(b1) {
+        uint textureIndex;
+    };
+    #else
+    Texture2DArray texture0: register(t0);
+    #endif
+
+    float4 main(PS_INPUT input): SV_Target {
+    #ifdef IMGUI_BINDLESS
+        Texture2DArray texture0 = ResourceDescriptorHeap[textureIndex];
+    #endif
+        return input.col * texture0.Sample(sampler0, float3(input.uv, 0));
+    }
+    ";
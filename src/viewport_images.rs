@@ -0,0 +1,193 @@
+//
+// Offscreen render targets that the app renders into and the UI draws, sized
+// to fit the windows that show them.
+//
+
+use imgui::TextureId;
+use windows::{
+    core::{Result, HSTRING},
+    Win32::Graphics::{
+        Direct3D12::{
+            ID3D12DescriptorHeap, ID3D12Device, ID3D12GraphicsCommandList, ID3D12Resource,
+            D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_DESCRIPTOR_HEAP_DESC,
+            D3D12_DESCRIPTOR_HEAP_FLAG_NONE, D3D12_DESCRIPTOR_HEAP_TYPE_RTV, D3D12_HEAP_FLAG_NONE,
+            D3D12_HEAP_PROPERTIES, D3D12_HEAP_TYPE_DEFAULT, D3D12_RESOURCE_BARRIER,
+            D3D12_RESOURCE_BARRIER_0, D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            D3D12_RESOURCE_BARRIER_TYPE_TRANSITION, D3D12_RESOURCE_DESC,
+            D3D12_RESOURCE_DIMENSION_TEXTURE2D, D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+            D3D12_RESOURCE_STATES, D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            D3D12_RESOURCE_STATE_RENDER_TARGET, D3D12_RESOURCE_TRANSITION_BARRIER,
+        },
+        Dxgi::Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+    },
+};
+
+/// A render target returned by `Renderer::viewport_image`.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewportImage {
+    /// Draws the render target, for example with `imgui::Image`.
+    pub texture_id: TextureId,
+
+    /// The view to render into before `render_draw_data`.
+    pub rtv: D3D12_CPU_DESCRIPTOR_HANDLE,
+
+    /// The render target's size in pixels.
+    pub size: [u32; 2],
+
+    /// The size to draw the image at, in imgui's coordinates.
+    pub display_size: [f32; 2],
+}
+
+#[derive(Default)]
+pub(crate) struct ViewportImages {
+    pub(crate) targets: Vec<ViewportTarget>,
+}
+
+pub(crate) struct ViewportTarget {
+    pub(crate) id: String,
+    pub(crate) format: DXGI_FORMAT,
+    pub(crate) size: [u32; 2],
+    pub(crate) resource: ID3D12Resource,
+    pub(crate) rtv_heap: ID3D12DescriptorHeap,
+    pub(crate) texture_id: TextureId,
+    // Whether viewport_image has asked for this target since the last
+    // new_frame
+    pub(crate) used: bool,
+}
+
+impl ViewportTarget {
+    pub(crate) fn image(&self, display_size: [f32; 2]) -> ViewportImage {
+        ViewportImage {
+            texture_id: self.texture_id,
+            rtv: unsafe { self.rtv_heap.GetCPUDescriptorHandleForHeapStart() },
+            size: self.size,
+            display_size,
+        }
+    }
+}
+
+impl ViewportImages {
+    pub(crate) fn position(&self, id: &str) -> Option<usize> {
+        self.targets.iter().position(|target| target.id == id)
+    }
+
+    // Removes the targets that weren't asked for last frame, and starts
+    // tracking which are asked for this frame.
+    pub(crate) fn begin_frame(&mut self) -> Vec<ViewportTarget> {
+        let (kept, unused) = std::mem::take(&mut self.targets)
+            .into_iter()
+            .partition(|target| target.used);
+        self.targets = kept;
+
+        for target in &mut self.targets {
+            target.used = false;
+        }
+
+        unused
+    }
+
+    // Between frames the targets are render targets for the app, but while
+    // the UI is drawn they're shader resources.
+    fn transition(
+        &self,
+        graphics_command_list: &ID3D12GraphicsCommandList,
+        state_before: D3D12_RESOURCE_STATES,
+        state_after: D3D12_RESOURCE_STATES,
+    ) {
+        if self.targets.is_empty() {
+            return;
+        }
+
+        let barriers: Vec<D3D12_RESOURCE_BARRIER> = self
+            .targets
+            .iter()
+            .map(|target| D3D12_RESOURCE_BARRIER {
+                Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+                Anonymous: D3D12_RESOURCE_BARRIER_0 {
+                    Transition: std::mem::ManuallyDrop::new(D3D12_RESOURCE_TRANSITION_BARRIER {
+                        pResource: unsafe { std::mem::transmute_copy(&target.resource) },
+                        Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+                        StateBefore: state_before,
+                        StateAfter: state_after,
+                    }),
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        unsafe { graphics_command_list.ResourceBarrier(&barriers) };
+    }
+
+    pub(crate) fn begin_ui(&self, graphics_command_list: &ID3D12GraphicsCommandList) {
+        self.transition(
+            graphics_command_list,
+            D3D12_RESOURCE_STATE_RENDER_TARGET,
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+        );
+    }
+
+    pub(crate) fn end_ui(&self, graphics_command_list: &ID3D12GraphicsCommandList) {
+        self.transition(
+            graphics_command_list,
+            D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+            D3D12_RESOURCE_STATE_RENDER_TARGET,
+        );
+    }
+}
+
+// Creates a render target in the render target state, with a view of it in a
+// heap of its own.
+pub(crate) fn create_viewport_target(
+    device: &ID3D12Device,
+    id: &str,
+    format: DXGI_FORMAT,
+    size: [u32; 2],
+) -> Result<(ID3D12Resource, ID3D12DescriptorHeap)> {
+    let mut resource: Option<ID3D12Resource> = None;
+    unsafe {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: size[0] as u64,
+                Height: size[1],
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_RENDER_TARGET,
+            None,
+            &mut resource,
+        )?;
+    }
+    let resource = resource.unwrap();
+    unsafe { resource.SetName(&HSTRING::from(format!("imgui viewport image {id}"))) }?;
+
+    let rtv_heap: ID3D12DescriptorHeap = unsafe {
+        device.CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
+            Type: D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
+            NumDescriptors: 1,
+            Flags: D3D12_DESCRIPTOR_HEAP_FLAG_NONE,
+            NodeMask: 0,
+        })
+    }?;
+    unsafe {
+        device.CreateRenderTargetView(
+            &resource,
+            None,
+            rtv_heap.GetCPUDescriptorHandleForHeapStart(),
+        );
+    }
+
+    Ok((resource, rtv_heap))
+}